@@ -0,0 +1,93 @@
+//! Compares the time taken to collect the files of a large generated crate when scanning files
+//! for module declarations and when parsing them in full.
+//!
+//! Run with `cargo bench -p cargo-files-core`.
+
+use cargo_files_core::{Options, ParseMode, Target, get_target_inputs, get_targets};
+use std::fmt::Write;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The number of modules in the generated crate, each in its own file.
+const MODULES: usize = 200;
+
+/// The number of functions in each module.
+const FUNCTIONS: usize = 50;
+
+/// The number of times files are collected in each mode.
+const ITERATIONS: usize = 5;
+
+fn main() {
+    let krate = tempfile::tempdir().expect("failed to create a temporary directory");
+    generate_crate(krate.path());
+    let target = get_targets(Some(&krate.path().join("Cargo.toml")))
+        .expect("failed to get targets")
+        .pop_first()
+        .expect("the crate has a target");
+
+    let full = measure(&target, ParseMode::Full);
+    let fast = measure(&target, ParseMode::Fast);
+    println!("full: {full:?} per iteration");
+    println!("fast: {fast:?} per iteration");
+    println!("speedup: {:.1}x", full.as_secs_f64() / fast.as_secs_f64());
+}
+
+/// Return the mean time taken to collect the files of the target, without caching.
+fn measure(target: &Target, parse_mode: ParseMode) -> Duration {
+    let options = Options {
+        jobs: NonZeroUsize::new(1),
+        parse_mode,
+        ..Options::default()
+    };
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let inputs = get_target_inputs(target, &options).expect("failed to collect files");
+        assert_eq!(inputs.sources.len(), MODULES + 1);
+    }
+    start.elapsed() / ITERATIONS as u32
+}
+
+/// Write a library whose modules are full of function bodies, as real crates are.
+fn generate_crate(root: &Path) {
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"generated\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+
+    let mut lib = String::new();
+    for module in 0..MODULES {
+        writeln!(lib, "#[cfg(any(unix, windows))]\npub mod module_{module};").unwrap();
+
+        let mut source = String::new();
+        for function in 0..FUNCTIONS {
+            writeln!(
+                source,
+                r#"
+/// Returns a value computed from `input`.
+pub fn function_{function}(input: &[u64]) -> Option<u64> {{
+    let mut total = 0u64;
+    for (index, value) in input.iter().enumerate() {{
+        if !(index % 3 == 0) && *value > {function} {{
+            total = total.wrapping_add(value.pow(2) >> 1);
+        }} else {{
+            match value.checked_sub(index as u64) {{
+                Some(rest) => total ^= rest,
+                None => return None,
+            }}
+        }}
+    }}
+    let closure = |x: u64| -> u64 {{ x.rotate_left(3) | (x & 0xff) }};
+    Some(closure(total))
+}}"#
+            )
+            .unwrap();
+        }
+        fs::write(root.join(format!("src/module_{module}.rs")), source).unwrap();
+    }
+    fs::write(root.join("src/lib.rs"), lib).unwrap();
+}
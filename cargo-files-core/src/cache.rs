@@ -0,0 +1,144 @@
+use crate::ParseMode;
+use crate::parser::ModVisitor;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The name of the file storing the declarations of each parsed file within the cache directory.
+const DECLARATIONS_FILE: &str = "declarations.json";
+
+/// Changes whenever the format of cached data changes, or the way files are parsed could.
+const CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The declarations of previously parsed files, stored on disk between runs.
+///
+/// An entry is only used if the size and modification time of its file are unchanged.
+#[derive(Debug)]
+pub(crate) struct DiskCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    /// Whether there are entries which haven't been saved yet.
+    dirty: AtomicBool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    /// How the file was parsed.
+    mode: ParseMode,
+    declarations: Arc<ModVisitor>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: String,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl DiskCache {
+    /// Load the cache stored in the given directory.
+    ///
+    /// A cache which is missing, unreadable or written by a different version of this crate is
+    /// treated as empty.
+    pub(crate) fn load(dir: &Path) -> Self {
+        let path = dir.join(DECLARATIONS_FILE);
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<CacheFile>(&contents).ok())
+            .filter(|cache| cache.version == CACHE_VERSION)
+            .map(|cache| cache.entries)
+            .unwrap_or_default();
+
+        DiskCache {
+            path,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Return the declarations of the given file, if they were cached since it last changed.
+    ///
+    /// Declarations found by scanning the file aren't used when it should be fully parsed, as
+    /// the file may not have been checked for syntax errors.
+    pub(crate) fn get(
+        &self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        mode: ParseMode,
+    ) -> Option<Arc<ModVisitor>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(path)?;
+        let unchanged =
+            metadata.modified().ok() == Some(entry.modified) && metadata.len() == entry.size;
+        let usable = entry.mode == mode || entry.mode == ParseMode::Full;
+        (unchanged && usable).then(|| Arc::clone(&entry.declarations))
+    }
+
+    /// Remember the declarations of the given file, as of the given metadata.
+    pub(crate) fn insert(
+        &self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        mode: ParseMode,
+        declarations: Arc<ModVisitor>,
+    ) {
+        // Without a modification time there's no way to tell whether the file has changed.
+        let Ok(modified) = metadata.modified() else {
+            return;
+        };
+        let entry = Entry {
+            modified,
+            size: metadata.len(),
+            mode,
+            declarations,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), entry);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the cache to disk, if anything has been added to it.
+    ///
+    /// Entries for files which no longer exist are dropped first, so that the cache doesn't grow
+    /// without bound as files are deleted or renamed.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|path, _| path.exists());
+        let cache = CacheFileRef {
+            version: CACHE_VERSION,
+            entries: &entries,
+        };
+        let contents = serde_json::to_vec(&cache)?;
+
+        // Write to a temporary file first, so that concurrent runs never see a partial cache.
+        let dir = self
+            .path
+            .parent()
+            .expect("cache file is within a directory");
+        fs::create_dir_all(dir)?;
+        let temporary = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Borrowed form of [`CacheFile`], to avoid cloning the entries when saving.
+#[derive(Serialize)]
+struct CacheFileRef<'a> {
+    version: &'a str,
+    entries: &'a HashMap<PathBuf, Entry>,
+}
@@ -0,0 +1,296 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::process::Command;
+use syn::punctuated::Punctuated;
+use syn::{Expr, ExprLit, Lit, Meta, Token};
+
+/// A single active cfg option, e.g. `unix` or `feature = "tls"`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum CfgOption {
+    /// A bare option, such as `unix`, `test` or `debug_assertions`.
+    Name(String),
+    /// A key-value option, such as `target_os = "linux"` or `feature = "tls"`.
+    KeyValue(String, String),
+}
+
+impl CfgOption {
+    /// Parse an option in the format accepted by `rustc --cfg`, i.e. `name` or `key="value"`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        match input.split_once('=') {
+            None => is_identifier(input).then(|| CfgOption::Name(input.to_string())),
+            Some((key, value)) => {
+                let key = key.trim();
+                let value = value.trim();
+                let value = value.strip_prefix('"')?.strip_suffix('"')?;
+                is_identifier(key).then(|| CfgOption::KeyValue(key.to_string(), value.to_string()))
+            }
+        }
+    }
+
+    /// Create a `feature = "..."` option.
+    pub fn feature(name: impl Into<String>) -> Self {
+        CfgOption::KeyValue(String::from("feature"), name.into())
+    }
+}
+
+impl fmt::Display for CfgOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfgOption::Name(name) => write!(f, "{name}"),
+            CfgOption::KeyValue(key, value) => write!(f, "{key}=\"{value}\""),
+        }
+    }
+}
+
+fn is_identifier(input: &str) -> bool {
+    let mut chars = input.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_alphabetic())
+        && chars.all(|c| c == '_' || c.is_alphanumeric())
+}
+
+/// A parsed cfg predicate, e.g. `all(unix, not(feature = "tls"))`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CfgExpr {
+    /// A predicate which holds if the option is active.
+    Option(CfgOption),
+    /// Holds if every nested predicate holds; `all()` is always true.
+    All(Vec<CfgExpr>),
+    /// Holds if any nested predicate holds; `any()` is always false.
+    Any(Vec<CfgExpr>),
+    /// Holds if the nested predicate does not hold.
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    /// Parse a predicate from its source representation, e.g. `any(unix, windows)`.
+    pub fn parse(input: &str) -> Result<Self, syn::Error> {
+        let meta: Meta = syn::parse_str(input)?;
+        Self::from_meta(&meta)
+    }
+
+    pub(crate) fn from_meta(meta: &Meta) -> Result<Self, syn::Error> {
+        match meta {
+            Meta::Path(path) => {
+                let ident = path.get_ident().ok_or_else(|| {
+                    syn::Error::new_spanned(path, "cfg option must be an identifier")
+                })?;
+                Ok(CfgExpr::Option(CfgOption::Name(ident.to_string())))
+            }
+            Meta::NameValue(name_value) => {
+                let ident = name_value.path.get_ident().ok_or_else(|| {
+                    syn::Error::new_spanned(&name_value.path, "cfg key must be an identifier")
+                })?;
+                let Expr::Lit(ExprLit {
+                    lit: Lit::Str(lit), ..
+                }) = &name_value.value
+                else {
+                    return Err(syn::Error::new_spanned(
+                        &name_value.value,
+                        "cfg value must be a string literal",
+                    ));
+                };
+                Ok(CfgExpr::Option(CfgOption::KeyValue(
+                    ident.to_string(),
+                    lit.value(),
+                )))
+            }
+            Meta::List(list) => {
+                let nested = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?
+                    .iter()
+                    .map(Self::from_meta)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if list.path.is_ident("all") {
+                    Ok(CfgExpr::All(nested))
+                } else if list.path.is_ident("any") {
+                    Ok(CfgExpr::Any(nested))
+                } else if list.path.is_ident("not") {
+                    let [inner] = <[CfgExpr; 1]>::try_from(nested).map_err(|_| {
+                        syn::Error::new_spanned(list, "not() takes exactly one predicate")
+                    })?;
+                    Ok(CfgExpr::Not(Box::new(inner)))
+                } else {
+                    Err(syn::Error::new_spanned(
+                        &list.path,
+                        "expected one of `all`, `any` or `not`",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for CfgExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, name: &str, nested: &[CfgExpr]| {
+            write!(f, "{name}(")?;
+            for (i, expr) in nested.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{expr}")?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            CfgExpr::Option(option) => write!(f, "{option}"),
+            CfgExpr::All(nested) => list(f, "all", nested),
+            CfgExpr::Any(nested) => list(f, "any", nested),
+            CfgExpr::Not(inner) => write!(f, "not({inner})"),
+        }
+    }
+}
+
+/// The set of active cfg options that predicates are evaluated against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    options: BTreeSet<CfgOption>,
+}
+
+impl CfgSet {
+    /// Create an empty set, in which only predicates like `not(unix)` hold.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the options rustc sets when compiling for the given target triple, or for the host
+    /// if none is given, as printed by `rustc --print cfg`.
+    ///
+    /// The `RUSTC` environment variable is respected, as it is by cargo.
+    pub fn from_rustc(target: Option<&str>) -> Result<Self, Error> {
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc"));
+        let mut command = Command::new(rustc);
+        command.args(["--print", "cfg"]);
+        if let Some(target) = target {
+            command.args(["--target", target]);
+        }
+
+        let output = command.output().map_err(Error::RustcError)?;
+        if !output.status.success() {
+            // Only the first line of rustc's error is interesting, the rest being help.
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().next().unwrap_or_default();
+            let message = message.strip_prefix("error: ").unwrap_or(message);
+            return Err(Error::RustcError(io::Error::other(message.to_string())));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(CfgOption::parse)
+            .collect())
+    }
+
+    /// Mark the given option as active.
+    pub fn insert(&mut self, option: CfgOption) {
+        self.options.insert(option);
+    }
+
+    /// Return true if the given option is active.
+    pub fn contains(&self, option: &CfgOption) -> bool {
+        self.options.contains(option)
+    }
+
+    /// Iterate over the active options, in sorted order.
+    pub fn iter(&self) -> impl Iterator<Item = &CfgOption> {
+        self.options.iter()
+    }
+
+    /// Evaluate the given predicate against this set.
+    pub fn is_active(&self, expr: &CfgExpr) -> bool {
+        match expr {
+            CfgExpr::Option(option) => self.contains(option),
+            CfgExpr::All(exprs) => exprs.iter().all(|expr| self.is_active(expr)),
+            CfgExpr::Any(exprs) => exprs.iter().any(|expr| self.is_active(expr)),
+            CfgExpr::Not(expr) => !self.is_active(expr),
+        }
+    }
+}
+
+impl FromIterator<CfgOption> for CfgSet {
+    fn from_iter<I: IntoIterator<Item = CfgOption>>(iter: I) -> Self {
+        CfgSet {
+            options: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<CfgOption> for CfgSet {
+    fn extend<I: IntoIterator<Item = CfgOption>>(&mut self, iter: I) {
+        self.options.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg_set(options: &[&str]) -> CfgSet {
+        options
+            .iter()
+            .map(|option| CfgOption::parse(option).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_option_parsing() {
+        assert_eq!(
+            CfgOption::parse("unix"),
+            Some(CfgOption::Name(String::from("unix")))
+        );
+        assert_eq!(
+            CfgOption::parse("target_os=\"linux\""),
+            Some(CfgOption::KeyValue(
+                String::from("target_os"),
+                String::from("linux")
+            ))
+        );
+        assert_eq!(CfgOption::parse("feature=tls"), None);
+        assert_eq!(CfgOption::parse("1abc"), None);
+    }
+
+    #[test]
+    fn test_expr_parsing() {
+        assert_eq!(
+            CfgExpr::parse(r#"all(unix, not(feature = "tls"))"#).unwrap(),
+            CfgExpr::All(vec![
+                CfgExpr::Option(CfgOption::Name(String::from("unix"))),
+                CfgExpr::Not(Box::new(CfgExpr::Option(CfgOption::feature("tls")))),
+            ])
+        );
+        assert!(CfgExpr::parse("not(unix, windows)").is_err());
+        assert!(CfgExpr::parse("maybe(unix)").is_err());
+    }
+
+    #[test]
+    fn test_expr_display() {
+        let display = |input: &str| CfgExpr::parse(input).unwrap().to_string();
+        assert_eq!(
+            display(r#"all(unix, not(feature = "tls"))"#),
+            r#"all(unix, not(feature="tls"))"#
+        );
+        assert_eq!(display("any()"), "any()");
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let cfg = cfg_set(&["unix", "target_os=\"linux\"", "feature=\"tls\""]);
+
+        let active = |input: &str| cfg.is_active(&CfgExpr::parse(input).unwrap());
+        assert!(active("unix"));
+        assert!(!active("windows"));
+        assert!(active(r#"target_os = "linux""#));
+        assert!(!active(r#"target_os = "macos""#));
+        assert!(active(r#"all(unix, feature = "tls")"#));
+        assert!(!active(r#"all(unix, feature = "json")"#));
+        assert!(active("any(windows, unix)"));
+        assert!(active("not(windows)"));
+        assert!(active("all()"));
+        assert!(!active("any()"));
+    }
+}
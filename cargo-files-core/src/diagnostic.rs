@@ -0,0 +1,67 @@
+use crate::Error;
+use crate::span::Span;
+use std::fmt;
+use std::path::Path;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Something which is likely a mistake, but which doesn't affect the collected files.
+    Warning,
+    /// Something which prevented some files from being collected.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem encountered while collecting files, which didn't stop the collection.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The underlying problem.
+    pub error: Error,
+}
+
+impl Diagnostic {
+    pub fn error(error: Error) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            error,
+        }
+    }
+
+    pub fn warning(error: Error) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            error,
+        }
+    }
+
+    /// Return the file this diagnostic refers to, if any.
+    pub fn file(&self) -> Option<&Path> {
+        self.error.file()
+    }
+
+    /// Return the location within the file this diagnostic refers to, if any.
+    pub fn span(&self) -> Option<Span> {
+        self.error.span()
+    }
+
+    /// Return a human-readable description of this diagnostic.
+    pub fn message(&self) -> String {
+        self.error.to_string()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.error)
+    }
+}
@@ -0,0 +1,91 @@
+//! Resolving cargo's `--features`, `--all-features` and `--no-default-features` flags against
+//! the `[features]` table of each package.
+
+use crate::{Error, Target};
+use std::collections::BTreeSet;
+
+/// The features to enable in each package, as selected by cargo's feature flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureSelection {
+    /// Features to enable, as with `--features`.
+    ///
+    /// A feature may be prefixed with a package name, as in `foo/tls`, to only enable it in that
+    /// package. Other features are enabled in every package which has them.
+    pub features: Vec<String>,
+    /// Enable every feature of every package, as with `--all-features`.
+    pub all_features: bool,
+    /// Don't enable the `default` feature, as with `--no-default-features`.
+    pub no_default_features: bool,
+}
+
+impl FeatureSelection {
+    /// Return the features enabled in the package containing the given target.
+    ///
+    /// This includes features enabled by other features, and the implicit features of optional
+    /// dependencies enabled using `dep_name/feature`. Optional dependencies enabled using
+    /// `dep:dep_name` don't have a feature of their own, so aren't included.
+    pub fn resolve(&self, target: &Target) -> BTreeSet<String> {
+        let mut pending = if self.all_features {
+            target.features.keys().cloned().collect::<Vec<_>>()
+        } else {
+            let mut requested = self
+                .features
+                .iter()
+                .filter_map(|feature| match feature.split_once('/') {
+                    Some((package, feature)) => (package == target.package).then_some(feature),
+                    None => Some(feature.as_str()),
+                })
+                .map(String::from)
+                .collect::<Vec<_>>();
+            if !self.no_default_features {
+                requested.push(String::from("default"));
+            }
+            requested
+        };
+
+        let mut enabled = BTreeSet::new();
+        while let Some(feature) = pending.pop() {
+            let Some(enables) = target.features.get(&feature) else {
+                continue;
+            };
+            if !enabled.insert(feature) {
+                continue;
+            }
+
+            for entry in enables {
+                if entry.starts_with("dep:") {
+                    continue;
+                }
+                match entry.split_once('/') {
+                    // `dep_name?/feature` only enables a feature of a dependency which is already
+                    // enabled, while `dep_name/feature` enables the dependency too.
+                    Some((dependency, _)) if !dependency.ends_with('?') => {
+                        pending.push(dependency.to_string());
+                    }
+                    Some(_) => {}
+                    None => pending.push(entry.clone()),
+                }
+            }
+        }
+        enabled
+    }
+
+    /// Check that every requested feature exists in a package containing one of the targets.
+    pub fn check<'a>(&self, targets: impl IntoIterator<Item = &'a Target>) -> Result<(), Error> {
+        let targets = targets.into_iter().collect::<Vec<_>>();
+        for requested in &self.features {
+            let (package, feature) = match requested.split_once('/') {
+                Some((package, feature)) => (Some(package), feature),
+                None => (None, requested.as_str()),
+            };
+            let found = targets.iter().any(|target| {
+                package.is_none_or(|package| package == target.package)
+                    && target.features.contains_key(feature)
+            });
+            if !found {
+                return Err(Error::FeatureNotFound(requested.clone()));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Selecting targets in the same way as cargo's `--package`, `--lib`, `--bin` and similar flags.
+
+use crate::{Error, Target};
+use cargo_metadata::TargetKind;
+use std::collections::BTreeSet;
+
+/// A selection of the targets of one kind, such as binaries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TargetSelection {
+    /// No targets of this kind are selected.
+    #[default]
+    None,
+    /// Every target of this kind is selected, as with `--bins`.
+    All,
+    /// The targets of this kind with the given names are selected, as with `--bin NAME`.
+    Named(Vec<String>),
+}
+
+impl TargetSelection {
+    fn is_none(&self) -> bool {
+        *self == TargetSelection::None
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            TargetSelection::None => false,
+            TargetSelection::All => true,
+            TargetSelection::Named(names) => names.iter().any(|selected| selected == name),
+        }
+    }
+}
+
+/// Selects targets by package and kind, as cargo's target selection flags do.
+///
+/// Packages are selected first; then, if any kind of target is selected, only targets of the
+/// selected kinds are kept. The default filter selects every target, including build scripts.
+#[derive(Debug, Clone, Default)]
+pub struct TargetFilter {
+    /// Only select targets of the packages with these names, as with `--package`. Every package
+    /// is selected if this is empty.
+    pub packages: Vec<String>,
+    /// Only select targets of workspace members, rather than of path dependencies outside the
+    /// workspace, as with `--workspace`.
+    pub workspace: bool,
+    /// Don't select targets of the packages with these names, as with `--exclude`.
+    pub exclude: Vec<String>,
+    /// Select library targets, as with `--lib`.
+    pub lib: bool,
+    /// The binary targets to select, as with `--bin` and `--bins`.
+    pub bins: TargetSelection,
+    /// The example targets to select, as with `--example` and `--examples`.
+    pub examples: TargetSelection,
+    /// The integration test targets to select, as with `--test` and `--tests`.
+    pub tests: TargetSelection,
+    /// The benchmark targets to select, as with `--bench` and `--benches`.
+    pub benches: TargetSelection,
+}
+
+impl TargetFilter {
+    /// Select every library, binary, example, test and benchmark target, as with
+    /// `--all-targets`. Unlike the default filter, this doesn't select build scripts.
+    pub fn all_targets() -> Self {
+        TargetFilter {
+            lib: true,
+            bins: TargetSelection::All,
+            examples: TargetSelection::All,
+            tests: TargetSelection::All,
+            benches: TargetSelection::All,
+            ..TargetFilter::default()
+        }
+    }
+
+    /// Return true if the given target is selected.
+    pub fn matches(&self, target: &Target) -> bool {
+        self.matches_package(target) && self.matches_kind(target)
+    }
+
+    /// Return the selected targets.
+    ///
+    /// Like cargo, it is an error to select a package or a named target which doesn't exist.
+    pub fn apply(&self, targets: BTreeSet<Target>) -> Result<BTreeSet<Target>, Error> {
+        for package in &self.packages {
+            if !targets.iter().any(|target| target.package == *package) {
+                return Err(Error::PackageNotFound(package.clone()));
+            }
+        }
+
+        let selections = [
+            ("bin", &self.bins),
+            ("example", &self.examples),
+            ("test", &self.tests),
+            ("bench", &self.benches),
+        ];
+        for (kind, selection) in selections {
+            let TargetSelection::Named(names) = selection else {
+                continue;
+            };
+            for name in names {
+                let found = targets.iter().any(|target| {
+                    self.matches_package(target)
+                        && kind_name(&target.kind) == Some(kind)
+                        && target.name == *name
+                });
+                if !found {
+                    return Err(Error::TargetNotFound {
+                        kind: kind.to_string(),
+                        name: name.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(targets
+            .into_iter()
+            .filter(|target| self.matches(target))
+            .collect())
+    }
+
+    fn matches_package(&self, target: &Target) -> bool {
+        (self.packages.is_empty() || self.packages.contains(&target.package))
+            && (!self.workspace || target.workspace_member)
+            && !self.exclude.contains(&target.package)
+    }
+
+    fn matches_kind(&self, target: &Target) -> bool {
+        let selects_kinds = self.lib
+            || !self.bins.is_none()
+            || !self.examples.is_none()
+            || !self.tests.is_none()
+            || !self.benches.is_none();
+        if !selects_kinds {
+            return true;
+        }
+
+        match kind_name(&target.kind) {
+            Some("lib") => self.lib,
+            Some("bin") => self.bins.matches(&target.name),
+            Some("example") => self.examples.matches(&target.name),
+            Some("test") => self.tests.matches(&target.name),
+            Some("bench") => self.benches.matches(&target.name),
+            _ => false,
+        }
+    }
+}
+
+/// Return the name cargo uses for the given kind of target in its selection flags, or `None`
+/// for build scripts and unknown kinds.
+fn kind_name(kind: &TargetKind) -> Option<&'static str> {
+    match kind {
+        TargetKind::Lib
+        | TargetKind::RLib
+        | TargetKind::DyLib
+        | TargetKind::CDyLib
+        | TargetKind::StaticLib
+        | TargetKind::ProcMacro => Some("lib"),
+        TargetKind::Bin => Some("bin"),
+        TargetKind::Example => Some("example"),
+        TargetKind::Test => Some("test"),
+        TargetKind::Bench => Some("bench"),
+        _ => None,
+    }
+}
@@ -1,14 +1,39 @@
 // Inspired by  https://github.com/rust-lang/rustfmt
+mod cache;
+pub mod cfg;
+pub mod diagnostic;
+pub mod features;
+pub mod filter;
+mod metadata;
+pub mod orphans;
+pub mod owner;
+mod parallel;
 pub mod parser;
+pub mod paths;
+mod scanner;
+pub mod session;
+pub mod span;
+pub mod tree;
 
-use crate::parser::extract_crate_files;
-pub use cargo_metadata::Edition;
+pub use crate::cfg::{CfgExpr, CfgOption, CfgSet};
+pub use crate::diagnostic::{Diagnostic, Severity};
+pub use crate::features::FeatureSelection;
+pub use crate::filter::{TargetFilter, TargetSelection};
+pub use crate::orphans::{find_orphan_files, get_orphan_files};
+pub use crate::owner::{Owner, OwnerIndex, ReachedBy, find_owners, get_file_owners};
+pub use crate::paths::PathStyle;
+pub use crate::session::Session;
+pub use crate::span::{LineColumn, Span};
+pub use crate::tree::{Declaration, ModuleId, ModuleNode, ModuleSource, ModuleTree};
+pub use cargo_metadata::{CrateType, Edition, PackageId, TargetKind};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io::{self};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use cargo_metadata::TargetKind;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,25 +42,283 @@ pub enum Error {
     ManifestNotCargoToml,
     #[error("no targets were found")]
     NoTargets,
+    #[error("package `{0}` not found")]
+    PackageNotFound(String),
+    #[error("no {kind} target named `{name}`")]
+    TargetNotFound { kind: String, name: String },
+    #[error("none of the selected packages have feature `{0}`")]
+    FeatureNotFound(String),
+    #[error("there was an error running rustc: {0}")]
+    RustcError(io::Error),
     #[error("there was an error reading Cargo.toml: {0}")]
     ManifestError(io::Error),
     #[error("there was an error reading {0}: {1}")]
     FileError(PathBuf, io::Error),
-    #[error("there was an error parsing a source file: {0}")]
-    ParseError(#[from] syn::Error),
-    #[error("could not find module")]
-    ModuleNotFound,
+    #[error("there was an error parsing {}: {message}", file.display())]
+    ParseError {
+        /// The file which could not be parsed.
+        file: PathBuf,
+        /// The location of the error within `file`.
+        span: Span,
+        message: String,
+    },
+    #[error(
+        "there was an error parsing {}, so its modules were found by scanning it instead: {message}",
+        file.display()
+    )]
+    ParsedWithRecovery {
+        /// The file which could not be parsed.
+        file: PathBuf,
+        /// The location of the error within `file`.
+        span: Span,
+        message: String,
+    },
+    #[error("could not find module `{name}` declared in {}", file.display())]
+    ModuleNotFound {
+        /// The file containing the module declaration.
+        file: PathBuf,
+        /// The location of the declaration within `file`.
+        span: Span,
+        /// The name of the module relative to `file`, e.g. `a::b` for `mod a { mod b; }`.
+        name: String,
+        /// Every path which was checked for the module's source file.
+        candidates: Vec<PathBuf>,
+    },
+    #[error(
+        "file for module `{name}` found at both {}",
+        candidates.iter().map(|c| c.display().to_string()).collect::<Vec<_>>().join(" and ")
+    )]
+    AmbiguousModule {
+        /// The file containing the module declaration.
+        file: PathBuf,
+        /// The location of the declaration within `file`.
+        span: Span,
+        /// The name of the module relative to `file`, e.g. `a::b` for `mod a { mod b; }`.
+        name: String,
+        /// The files which could contain the module, in order of preference.
+        candidates: Vec<PathBuf>,
+    },
+    #[error("could not find {} included from {}", path.display(), file.display())]
+    IncludeNotFound {
+        /// The file containing the `include!`-like macro invocation.
+        file: PathBuf,
+        /// The location of the invocation within `file`.
+        span: Span,
+        /// The path to the included file.
+        path: PathBuf,
+    },
+    #[error("module cycle detected: {0}")]
+    ModuleCycle(ModuleCycle),
     #[error("source file must have parent")]
     NoParent,
     #[error("source file must have a stem")]
     NoStem,
 }
 
+impl Error {
+    /// Return the file this error refers to, if any.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Error::FileError(file, _)
+            | Error::ParseError { file, .. }
+            | Error::ParsedWithRecovery { file, .. }
+            | Error::ModuleNotFound { file, .. }
+            | Error::AmbiguousModule { file, .. }
+            | Error::IncludeNotFound { file, .. } => Some(file),
+            Error::ModuleCycle(cycle) => cycle.steps.first().map(|(file, _)| file.as_path()),
+            _ => None,
+        }
+    }
+
+    /// Return the location within the file this error refers to, if any.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::ParseError { span, .. }
+            | Error::ParsedWithRecovery { span, .. }
+            | Error::ModuleNotFound { span, .. }
+            | Error::AmbiguousModule { span, .. }
+            | Error::IncludeNotFound { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    fn parse(file: &Path, error: &syn::Error) -> Self {
+        Error::ParseError {
+            file: file.to_path_buf(),
+            span: Span::from(error.span()),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// A chain of declarations which leads back to a file that is already being visited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleCycle {
+    /// Each file in the cycle, together with the declaration followed out of it.
+    ///
+    /// The declaration in the last step leads back to the file in the first step.
+    pub steps: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for ModuleCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (file, declaration) in &self.steps {
+            write!(f, "{} → {declaration} → ", file.display())?;
+        }
+        match self.steps.first() {
+            Some((file, _)) => write!(f, "{}", file.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Options controlling how the files of a target are collected.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// If set, modules whose `#[cfg(...)]` predicates don't hold under this cfg set are skipped.
+    pub cfg: Option<CfgSet>,
+    /// If set, problems such as unparseable files or missing modules are recorded in
+    /// [`TargetInputs::diagnostics`] and collection carries on, rather than returning an error.
+    pub keep_going: bool,
+    /// If set, problems which are usually warnings, such as a module which could be found at
+    /// both `foo.rs` and `foo/mod.rs`, are treated as errors.
+    pub strict: bool,
+    /// The number of threads used to parse files. Defaults to the available parallelism.
+    pub jobs: Option<NonZeroUsize>,
+    /// If set, the modules declared in each parsed file are stored in this directory, and
+    /// reused by later runs for files whose size and modification time are unchanged.
+    pub cache_dir: Option<PathBuf>,
+    /// How files are parsed to find the modules they declare.
+    pub parse_mode: ParseMode,
+    /// If set, the features this selection enables in the package of each target are added to
+    /// [`Options::cfg`] when collecting the files of that target.
+    ///
+    /// This has no effect without a cfg set, as the other cfg options of a build must be known
+    /// to tell which modules it compiles; see [`CfgSet::from_rustc`].
+    pub features: Option<FeatureSelection>,
+}
+
+impl Options {
+    /// Return the cfg set used to collect the files of the given target.
+    ///
+    /// Like cargo, which builds test and benchmark targets with `rustc --test`, this sets
+    /// `cfg(test)` for those targets.
+    pub fn target_cfg(&self, target: &Target) -> Option<CfgSet> {
+        let mut cfg = self.cfg.clone()?;
+        if matches!(target.kind, TargetKind::Test | TargetKind::Bench) {
+            cfg.insert(CfgOption::Name(String::from("test")));
+        }
+        if let Some(features) = &self.features {
+            cfg.extend(features.resolve(target).into_iter().map(CfgOption::feature));
+        }
+        Some(cfg)
+    }
+}
+
+/// How files are parsed to find the modules they declare.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParseMode {
+    /// Scan the tokens of each file for module declarations, without parsing items or
+    /// expressions. This is several times faster, but syntax errors are only reported for files
+    /// which can't be tokenized, such as those with unbalanced delimiters.
+    Fast,
+    /// Parse each file with syn, warning about any syntax error. This is the default.
+    ///
+    /// Files which syn can't parse, such as those using nightly-only syntax, are scanned as with
+    /// [`ParseMode::Fast`] and recorded in [`TargetInputs::recovered`]. With
+    /// [`Options::strict`], syntax errors are errors instead, and such files are not scanned.
+    #[default]
+    Full,
+}
+
+/// The files which make up a target.
+#[derive(Debug)]
+pub struct TargetInputs {
+    /// The module hierarchy of the target.
+    pub modules: ModuleTree,
+    /// Rust source files: the target root, its modules, and files pulled in using `include!`.
+    pub sources: HashSet<PathBuf>,
+    /// Files embedded using `include_str!` or `include_bytes!`.
+    pub assets: HashSet<PathBuf>,
+    /// Module files which syn couldn't parse, whose modules were instead found by scanning their
+    /// tokens, as with [`ParseMode::Fast`]. Each is also reported as a warning.
+    ///
+    /// Only [`ParseMode::Full`] parses files with syn, so this is always empty otherwise.
+    pub recovered: HashSet<PathBuf>,
+    /// Problems encountered while collecting files.
+    ///
+    /// Errors are only recorded here with [`Options::keep_going`], and are otherwise returned.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
 /// Get all source files for the given target.
 pub fn get_target_files(target: &Target) -> Result<HashSet<PathBuf>, Error> {
-    let mut acc = HashSet::new();
-    extract_crate_files(&target.path, &target.path, &mut acc)?;
-    Ok(acc)
+    Ok(get_target_inputs(target, &Options::default())?.sources)
+}
+
+/// Get the source files for the given target that are compiled under the given cfg set.
+///
+/// Modules whose `#[cfg(...)]` predicates don't hold are skipped, and need not exist on disk.
+pub fn get_target_files_with_cfg(target: &Target, cfg: &CfgSet) -> Result<HashSet<PathBuf>, Error> {
+    let options = Options {
+        cfg: Some(cfg.clone()),
+        ..Options::default()
+    };
+    Ok(get_target_inputs(target, &options)?.sources)
+}
+
+/// Get all source files and assets for the given target.
+pub fn get_target_inputs(target: &Target, options: &Options) -> Result<TargetInputs, Error> {
+    let mut inputs = get_all_target_inputs([target], options)?;
+    Ok(inputs.pop().expect("one target was given"))
+}
+
+/// Get all source files and assets for each of the given targets, in the same order.
+///
+/// Files shared between targets are only parsed once; use a [`Session`] to also share them
+/// between calls.
+pub fn get_all_target_inputs<'a>(
+    targets: impl IntoIterator<Item = &'a Target>,
+    options: &Options,
+) -> Result<Vec<TargetInputs>, Error> {
+    Session::new(options.clone()).all_target_inputs(targets)
+}
+
+/// Get the directory in which cargo-files caches data for the given cargo workspace, which is
+/// `cargo-files` within the workspace's target directory.
+///
+/// To avoid running cargo, the target directory is taken from `CARGO_TARGET_DIR` or assumed to
+/// be `target` next to the workspace's root manifest; `build.target-dir` in cargo config files
+/// isn't taken into account.
+pub fn get_cache_dir(manifest_path: Option<&Path>) -> Result<PathBuf, Error> {
+    if manifest_path.is_some_and(|path| !path.ends_with("Cargo.toml")) {
+        return Err(Error::ManifestNotCargoToml);
+    }
+
+    let target_dir = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(target_dir) => PathBuf::from(target_dir),
+        None => {
+            let manifest_path = metadata::find_manifest(manifest_path)?;
+            let workspace_root = metadata::find_workspace_root(&manifest_path);
+            workspace_root.with_file_name("target")
+        }
+    };
+    Ok(target_dir.join("cargo-files"))
+}
+
+/// Get the root directory of the given cargo workspace, which contains its root manifest.
+///
+/// Like [`get_cache_dir`], this doesn't run cargo, so `workspace.members` and
+/// `workspace.exclude` aren't taken into account.
+pub fn get_workspace_root(manifest_path: Option<&Path>) -> Result<PathBuf, Error> {
+    if manifest_path.is_some_and(|path| !path.ends_with("Cargo.toml")) {
+        return Err(Error::ManifestNotCargoToml);
+    }
+
+    let manifest_path = metadata::find_manifest(manifest_path)?;
+    let workspace_root = metadata::find_workspace_root(&manifest_path);
+    let dir = workspace_root.parent().unwrap_or(&workspace_root);
+    Ok(dunce::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()))
 }
 
 /// Get all targets within the given cargo workspace.
@@ -44,39 +327,106 @@ pub fn get_targets(manifest_path: Option<&Path>) -> Result<BTreeSet<Target>, Err
         if !specified_manifest_path.ends_with("Cargo.toml") {
             return Err(Error::ManifestNotCargoToml);
         }
-        _get_targets(Some(specified_manifest_path))
+        Ok(_get_targets(Some(specified_manifest_path))?.0)
     } else {
-        _get_targets(None)
+        Ok(_get_targets(None)?.0)
     }
 }
 
-/// Target uses a `path` field for equality and hashing.
-#[derive(Debug)]
+/// Get all targets within the given cargo workspace, reusing the targets found by a previous
+/// call with the same cache directory if possible.
+///
+/// Cached targets are reused until any `Cargo.toml`, `Cargo.lock` or `.cargo/config.toml` they
+/// were derived from changes, or a file is added to a directory cargo discovers targets in.
+pub fn get_targets_cached(
+    manifest_path: Option<&Path>,
+    cache_dir: &Path,
+) -> Result<BTreeSet<Target>, Error> {
+    if manifest_path.is_some_and(|path| !path.ends_with("Cargo.toml")) {
+        return Err(Error::ManifestNotCargoToml);
+    }
+
+    let key = metadata::find_manifest(manifest_path)?;
+    if let Some(targets) = metadata::load_targets(cache_dir, &key) {
+        return Ok(targets);
+    }
+
+    let (targets, inputs) = _get_targets(manifest_path)?;
+    // The cache only saves time, so failing to write it isn't worth failing over.
+    let _ = metadata::store_targets(cache_dir, &key, &targets, &inputs);
+    Ok(targets)
+}
+
+/// A target of a package, such as its library or one of its binaries.
+///
+/// Targets are identified by their package, name and kind, so that a binary and an example of
+/// the same name are different targets, as are several targets sharing a root file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Target {
+    /// The name of the package containing the target.
+    pub package: String,
+    /// The ID of the package containing the target, which is unique within a workspace.
+    pub package_id: PackageId,
+    /// The name of the target.
+    pub name: String,
     /// A path to the main source file of the target.
     pub path: PathBuf,
-    /// A kind of target (e.g., lib, bin, example, ...).
+    /// A path to the Cargo.toml of the package containing the target.
+    pub manifest_path: PathBuf,
+    /// The kind of target (e.g., lib, bin, example, ...), which is the first of [`Target::kinds`].
     pub kind: TargetKind,
+    /// Every kind of the target, such as `lib` and `cdylib` for a library built as both.
+    pub kinds: Vec<TargetKind>,
+    /// The crate types the target is built as.
+    pub crate_types: Vec<CrateType>,
+    /// The features which must be enabled for the target to be built.
+    pub required_features: Vec<String>,
+    /// Whether the target's documentation examples are tested.
+    pub doctest: bool,
+    /// Whether the target is tested by `cargo test` by default.
+    pub test: bool,
     /// Rust edition for this target.
     pub edition: Edition,
+    /// Whether the package containing the target is a member of the workspace, rather than a
+    /// path dependency outside it.
+    pub workspace_member: bool,
+    /// The features of the package containing the target, and the features or dependencies
+    /// each one enables.
+    pub features: BTreeMap<String, Vec<String>>,
 }
 
 impl Target {
-    pub fn from_target(target: &cargo_metadata::Target) -> Self {
+    pub fn from_target(package: &cargo_metadata::Package, target: &cargo_metadata::Target) -> Self {
         let path = PathBuf::from(&target.src_path);
         let canonicalized = dunce::canonicalize(&path).unwrap_or(path);
 
         Target {
+            package: package.name.to_string(),
+            package_id: package.id.clone(),
+            name: target.name.clone(),
             path: canonicalized,
+            manifest_path: PathBuf::from(&package.manifest_path),
             kind: target.kind[0].clone(),
+            kinds: target.kind.clone(),
+            crate_types: target.crate_types.clone(),
+            required_features: target.required_features.clone(),
+            doctest: target.doctest,
+            test: target.test,
             edition: target.edition,
+            workspace_member: true,
+            features: package.features.clone(),
         }
     }
+
+    /// The package, name and kind of the target, which together identify it.
+    fn key(&self) -> (&PackageId, &str, &TargetKind) {
+        (&self.package_id, &self.name, &self.kind)
+    }
 }
 
 impl PartialEq for Target {
     fn eq(&self, other: &Target) -> bool {
-        self.path == other.path
+        self.key() == other.key()
     }
 }
 
@@ -88,7 +438,7 @@ impl PartialOrd for Target {
 
 impl Ord for Target {
     fn cmp(&self, other: &Target) -> Ordering {
-        self.path.cmp(&other.path)
+        self.key().cmp(&other.key())
     }
 }
 
@@ -96,31 +446,43 @@ impl Eq for Target {}
 
 impl Hash for Target {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.path.hash(state);
+        self.key().hash(state);
     }
 }
 
-/// Get all targets from the specified manifest.
-fn _get_targets(manifest_path: Option<&Path>) -> Result<BTreeSet<Target>, Error> {
+/// Get all targets from the specified manifest, together with the files they were derived from.
+fn _get_targets(
+    manifest_path: Option<&Path>,
+) -> Result<(BTreeSet<Target>, BTreeSet<PathBuf>), Error> {
     let mut targets = BTreeSet::new();
-    get_targets_recursive(manifest_path, &mut targets, &mut BTreeSet::new())?;
+    let mut inputs = BTreeSet::new();
+    get_targets_recursive(
+        manifest_path,
+        true,
+        &mut targets,
+        &mut BTreeSet::new(),
+        &mut inputs,
+    )?;
 
     if targets.is_empty() {
         Err(Error::NoTargets)
     } else {
-        Ok(targets)
+        Ok((targets, inputs))
     }
 }
 
 fn get_targets_recursive(
     manifest_path: Option<&Path>,
+    workspace_member: bool,
     targets: &mut BTreeSet<Target>,
     visited: &mut BTreeSet<String>,
+    inputs: &mut BTreeSet<PathBuf>,
 ) -> Result<(), Error> {
     let metadata = get_cargo_metadata(manifest_path).map_err(Error::ManifestError)?;
+    inputs.extend(metadata::metadata_inputs(&metadata));
 
     for package in &metadata.packages {
-        add_targets(&package.targets, targets);
+        add_targets(package, workspace_member, targets);
 
         // Look for local dependencies using information available since cargo v1.51
         for dependency in &package.dependencies {
@@ -136,7 +498,7 @@ fn get_targets_recursive(
                     .any(|p| p.manifest_path.eq(&manifest_path))
             {
                 visited.insert(dependency.name.to_owned());
-                get_targets_recursive(Some(&manifest_path), targets, visited)?;
+                get_targets_recursive(Some(&manifest_path), false, targets, visited, inputs)?;
             }
         }
     }
@@ -144,9 +506,16 @@ fn get_targets_recursive(
     Ok(())
 }
 
-fn add_targets(target_paths: &[cargo_metadata::Target], targets: &mut BTreeSet<Target>) {
-    for target in target_paths {
-        targets.insert(Target::from_target(target));
+fn add_targets(
+    package: &cargo_metadata::Package,
+    workspace_member: bool,
+    targets: &mut BTreeSet<Target>,
+) {
+    for target in &package.targets {
+        targets.insert(Target {
+            workspace_member,
+            ..Target::from_target(package, target)
+        });
     }
 }
 
@@ -164,7 +533,7 @@ fn get_cargo_metadata(manifest_path: Option<&Path>) -> Result<cargo_metadata::Me
             cmd.other_options(vec![]);
             match cmd.exec() {
                 Ok(metadata) => Ok(metadata),
-                Err(error) => Err(io::Error::new(io::ErrorKind::Other, error.to_string())),
+                Err(error) => Err(io::Error::other(error.to_string())),
             }
         }
     }
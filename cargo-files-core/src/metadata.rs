@@ -0,0 +1,180 @@
+use crate::{Error, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The name of the file storing the targets of each workspace within the cache directory.
+const TARGETS_FILE: &str = "targets.json";
+
+/// Changes whenever the format of cached data changes, or the way targets are found could.
+const CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Return the files which affect the targets cargo reports for the given metadata.
+///
+/// These are the manifest of each package and of the workspace, the lock file, cargo config
+/// files, and the directories cargo looks in when discovering targets automatically.
+pub(crate) fn metadata_inputs(metadata: &cargo_metadata::Metadata) -> BTreeSet<PathBuf> {
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let mut inputs = BTreeSet::from([
+        workspace_root.join("Cargo.toml"),
+        workspace_root.join("Cargo.lock"),
+    ]);
+
+    for package in &metadata.packages {
+        let manifest_path = package.manifest_path.as_std_path();
+        inputs.insert(manifest_path.to_path_buf());
+        if let Some(root) = manifest_path.parent() {
+            // Adding a file to any of these directories may add a target.
+            inputs.insert(root.to_path_buf());
+            for dir in ["src", "src/bin", "examples", "tests", "benches"] {
+                inputs.insert(root.join(dir));
+            }
+        }
+    }
+
+    for dir in workspace_root.ancestors() {
+        inputs.insert(dir.join(".cargo").join("config.toml"));
+        inputs.insert(dir.join(".cargo").join("config"));
+    }
+    if let Some(cargo_home) = cargo_home() {
+        inputs.insert(cargo_home.join("config.toml"));
+        inputs.insert(cargo_home.join("config"));
+    }
+
+    inputs
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(cargo_home) = std::env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(cargo_home));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".cargo"))
+}
+
+/// Return the manifest cargo would use given the `--manifest-path` argument, which is the
+/// nearest `Cargo.toml` to the current directory if none is given.
+pub(crate) fn find_manifest(manifest_path: Option<&Path>) -> Result<PathBuf, Error> {
+    if let Some(manifest_path) = manifest_path {
+        return Ok(dunce::canonicalize(manifest_path).unwrap_or(manifest_path.to_path_buf()));
+    }
+
+    let cwd = std::env::current_dir().map_err(Error::ManifestError)?;
+    cwd.ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            Error::ManifestError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "could not find `Cargo.toml` in `{}` or any parent directory",
+                    cwd.display()
+                ),
+            ))
+        })
+}
+
+/// Return the root manifest of the workspace containing the given manifest.
+///
+/// As with cargo, this is the manifest named by `package.workspace`, or else the nearest
+/// manifest with a `[workspace]` table, starting with the manifest itself. If there is none,
+/// the manifest is its own root. Unlike cargo, `workspace.members` and `workspace.exclude`
+/// aren't checked.
+pub(crate) fn find_workspace_root(manifest_path: &Path) -> PathBuf {
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| contents.parse::<toml::Table>().ok())
+    };
+
+    if let Some(manifest) = read(manifest_path) {
+        let explicit_root = manifest
+            .get("package")
+            .and_then(|package| package.get("workspace"))
+            .and_then(|root| root.as_str());
+        if let (Some(root), Some(dir)) = (explicit_root, manifest_path.parent()) {
+            return dir.join(root).join("Cargo.toml");
+        }
+    }
+
+    let ancestors = manifest_path.parent().into_iter().flat_map(Path::ancestors);
+    ancestors
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|candidate| {
+            read(candidate).is_some_and(|manifest| manifest.contains_key("workspace"))
+        })
+        .unwrap_or_else(|| manifest_path.to_path_buf())
+}
+
+/// The size and modification time of a file, or `None` if it doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint(Option<(SystemTime, u64)>);
+
+impl Fingerprint {
+    fn of(path: &Path) -> Self {
+        let metadata = fs::metadata(path).ok();
+        Fingerprint(metadata.and_then(|metadata| Some((metadata.modified().ok()?, metadata.len()))))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    inputs: BTreeMap<PathBuf, Fingerprint>,
+    targets: BTreeSet<Target>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: String,
+    /// The targets found for each manifest.
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+/// Return the targets cached for the given manifest, if none of their inputs have changed.
+pub(crate) fn load_targets(cache_dir: &Path, manifest_path: &Path) -> Option<BTreeSet<Target>> {
+    let mut cache = read_cache(cache_dir)?;
+    let entry = cache.entries.remove(manifest_path)?;
+    let unchanged = entry
+        .inputs
+        .iter()
+        .all(|(path, fingerprint)| Fingerprint::of(path) == *fingerprint);
+    unchanged.then_some(entry.targets)
+}
+
+/// Cache the targets found for the given manifest, along with the files they were derived from.
+pub(crate) fn store_targets(
+    cache_dir: &Path,
+    manifest_path: &Path,
+    targets: &BTreeSet<Target>,
+    inputs: &BTreeSet<PathBuf>,
+) -> io::Result<()> {
+    let mut cache = read_cache(cache_dir).unwrap_or_else(|| CacheFile {
+        version: String::from(CACHE_VERSION),
+        ..CacheFile::default()
+    });
+    let entry = Entry {
+        inputs: inputs
+            .iter()
+            .map(|path| (path.clone(), Fingerprint::of(path)))
+            .collect(),
+        targets: targets.iter().cloned().collect(),
+    };
+    cache.entries.insert(manifest_path.to_path_buf(), entry);
+
+    // Write to a temporary file first, so that concurrent runs never see a partial cache.
+    fs::create_dir_all(cache_dir)?;
+    let path = cache_dir.join(TARGETS_FILE);
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temporary, serde_json::to_vec(&cache)?)?;
+    fs::rename(&temporary, &path)
+}
+
+fn read_cache(cache_dir: &Path) -> Option<CacheFile> {
+    let contents = fs::read(cache_dir.join(TARGETS_FILE)).ok()?;
+    serde_json::from_slice::<CacheFile>(&contents)
+        .ok()
+        .filter(|cache| cache.version == CACHE_VERSION)
+}
@@ -0,0 +1,70 @@
+use crate::{Error, Options, Target, get_all_target_inputs};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Return the Rust source files within the packages of the given targets which are not in
+/// `used`, typically the union of the sources of every target.
+///
+/// Each package directory is walked, skipping files ignored by `.gitignore`, hidden files, the
+/// `target` directory and any nested packages.
+pub fn find_orphan_files<'a>(
+    targets: impl IntoIterator<Item = &'a Target>,
+    used: &HashSet<PathBuf>,
+) -> Vec<PathBuf> {
+    let package_roots = targets
+        .into_iter()
+        .filter_map(|target| target.manifest_path.parent())
+        .collect::<BTreeSet<_>>();
+
+    let mut orphans = BTreeSet::new();
+    for root in package_roots {
+        for file in rust_files(root) {
+            let canonical_path = dunce::canonicalize(&file).unwrap_or(file);
+            if !used.contains(&canonical_path) {
+                orphans.insert(canonical_path);
+            }
+        }
+    }
+    orphans.into_iter().collect()
+}
+
+/// Return the Rust source files within the packages of the given targets which are not used
+/// by any of them, in sorted order.
+///
+/// Problems recorded with [`Options::keep_going`] are discarded, and may cause files to be
+/// reported as orphans; use [`get_all_target_inputs`] and [`find_orphan_files`] to inspect them.
+pub fn get_orphan_files<'a>(
+    targets: impl IntoIterator<Item = &'a Target> + Clone,
+    options: &Options,
+) -> Result<Vec<PathBuf>, Error> {
+    let used = get_all_target_inputs(targets.clone(), options)?
+        .into_iter()
+        .flat_map(|inputs| inputs.sources)
+        .collect();
+    Ok(find_orphan_files(targets, &used))
+}
+
+/// Walk the given package directory, returning every `.rs` file.
+fn rust_files(root: &Path) -> Vec<PathBuf> {
+    let root = root.to_path_buf();
+    let walker = ignore::WalkBuilder::new(&root)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            let path = entry.path();
+            if path == root || !entry.file_type().is_some_and(|t| t.is_dir()) {
+                return true;
+            }
+            let is_target_dir =
+                path.parent() == Some(root.as_path()) && entry.file_name() == "target";
+            !is_target_dir && !path.join("Cargo.toml").exists()
+        })
+        .build();
+
+    // Entries which can't be read, e.g. broken symlinks, can't be compiled either.
+    walker
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(ignore::DirEntry::into_path)
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rs"))
+        .collect()
+}
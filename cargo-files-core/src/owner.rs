@@ -0,0 +1,116 @@
+use crate::{Error, Options, Target, TargetInputs, get_all_target_inputs};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A target which uses a file, and the module the file belongs to within that target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner<'a> {
+    pub target: &'a Target,
+    /// The fully qualified path of the module, such as `crate::net::tcp`.
+    pub module_path: String,
+    /// How the file became part of the target.
+    pub reached_by: ReachedBy,
+}
+
+/// How a file became part of a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReachedBy {
+    /// The file is the root source file of the target.
+    Root,
+    /// The file was found using the name of a `mod` declaration.
+    Mod,
+    /// The file was found using a `#[path]` attribute on a `mod` declaration.
+    Path,
+    /// The file was pulled into a module using `include!`.
+    Include,
+}
+
+/// Return the owners of a file within a single target, given the inputs collected for it.
+///
+/// Use an [`OwnerIndex`] to find the owners of many files.
+pub fn find_owners<'a>(target: &'a Target, inputs: &TargetInputs, file: &Path) -> Vec<Owner<'a>> {
+    let file = &dunce::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let mut index = OwnerIndex::default();
+    index.add(target, inputs);
+    index.get(file).to_vec()
+}
+
+/// The owners of every file used by a set of targets, built by walking the module tree of each
+/// target once.
+#[derive(Debug, Default)]
+pub struct OwnerIndex<'a> {
+    owners: HashMap<PathBuf, Vec<Owner<'a>>>,
+}
+
+impl<'a> OwnerIndex<'a> {
+    /// Index the files of each of the given targets, in order.
+    pub fn new<'b>(targets: impl IntoIterator<Item = (&'a Target, &'b TargetInputs)>) -> Self {
+        let mut index = OwnerIndex::default();
+        for (target, inputs) in targets {
+            index.add(target, inputs);
+        }
+        index
+    }
+
+    /// Index the files of a single target, given the inputs collected for it.
+    pub fn add(&mut self, target: &'a Target, inputs: &TargetInputs) {
+        let modules = inputs.modules.iter().filter_map(|(_, node)| {
+            let reached_by = if node.parent.is_none() {
+                ReachedBy::Root
+            } else if node.has_path_attribute {
+                ReachedBy::Path
+            } else {
+                ReachedBy::Mod
+            };
+            Some((node.file()?, node, reached_by))
+        });
+        let including = inputs.modules.iter().flat_map(|(_, node)| {
+            let includes = node.includes.iter();
+            includes.map(move |file| (file.as_path(), node, ReachedBy::Include))
+        });
+
+        for (file, node, reached_by) in modules.chain(including) {
+            let owner = Owner {
+                target,
+                module_path: node.path.clone(),
+                reached_by,
+            };
+            // Without a cfg set, a module may appear several times with different candidate
+            // files.
+            let owners = self.owners.entry(file.to_path_buf()).or_default();
+            if !owners.contains(&owner) {
+                owners.push(owner);
+            }
+        }
+    }
+
+    /// Return the owners of the given file, which must be canonical like the files of a
+    /// [`ModuleTree`](crate::ModuleTree).
+    pub fn get(&self, file: &Path) -> &[Owner<'a>] {
+        self.owners.get(file).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Find the targets which use each of the given files.
+///
+/// Returns the owners of each file, in the same order as `files`. A file reached from several
+/// targets, e.g. using `#[path]`, has an owner for each of them. Problems recorded with
+/// [`Options::keep_going`] are discarded; use [`get_all_target_inputs`] and [`find_owners`] to
+/// inspect them.
+pub fn get_file_owners<'a>(
+    targets: impl IntoIterator<Item = &'a Target>,
+    files: &[PathBuf],
+    options: &Options,
+) -> Result<Vec<Vec<Owner<'a>>>, Error> {
+    let targets = targets.into_iter().collect::<Vec<_>>();
+    let inputs = get_all_target_inputs(targets.iter().copied(), options)?;
+
+    let index = OwnerIndex::new(targets.into_iter().zip(&inputs));
+    let owners = files.iter().map(|file| {
+        let file = dunce::canonicalize(file).unwrap_or_else(|_| file.clone());
+        index.get(&file).to_vec()
+    });
+    Ok(owners.collect())
+}
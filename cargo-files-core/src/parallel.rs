@@ -0,0 +1,139 @@
+use crate::cfg::CfgSet;
+use crate::parser::ParsedFiles;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+/// A file waiting to be parsed.
+struct Job<'a> {
+    /// The cfg set of the target the file was discovered from.
+    cfg: Option<&'a CfgSet>,
+    /// Identifies `cfg` among the distinct cfg sets of every target.
+    cfg_id: usize,
+    /// Whether modules declared in `path` are resolved from its own directory.
+    owns_directory: bool,
+    path: PathBuf,
+}
+
+#[derive(Default)]
+struct Queue<'a> {
+    jobs: VecDeque<Job<'a>>,
+    /// Every file which has been queued, with the cfg set it was queued with.
+    ///
+    /// A file reached under several cfg sets is queued once for each, as each may enable
+    /// different modules; parsing it again is cheap, as parsed files are remembered.
+    seen: HashSet<(PathBuf, usize)>,
+    /// The number of jobs currently being worked on.
+    active: usize,
+}
+
+/// Parse every file reachable from the given target roots, each with the cfg set of its
+/// target, using `threads` worker threads.
+///
+/// This only fills `files` so that the traversal of each target, which happens afterwards and
+/// in order, doesn't need to parse anything itself. That keeps the results identical to a
+/// serial traversal.
+pub(crate) fn parse_all(files: &ParsedFiles, roots: &[(&Path, Option<&CfgSet>)], threads: usize) {
+    let mut queue = Queue::default();
+    let mut cfgs = Vec::new();
+    for &(root_path, cfg) in roots {
+        let cfg_id = match cfgs.iter().position(|&other| other == cfg) {
+            Some(cfg_id) => cfg_id,
+            None => {
+                cfgs.push(cfg);
+                cfgs.len() - 1
+            }
+        };
+        if queue.seen.insert((root_path.to_path_buf(), cfg_id)) {
+            queue.jobs.push_back(Job {
+                cfg,
+                cfg_id,
+                owns_directory: true,
+                path: root_path.to_path_buf(),
+            });
+        }
+    }
+    let queue = Mutex::new(queue);
+    let changed = Condvar::new();
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| work(files, &queue, &changed));
+        }
+    });
+}
+
+fn work(files: &ParsedFiles, queue: &Mutex<Queue>, changed: &Condvar) {
+    loop {
+        let job = {
+            let mut queue = queue.lock().unwrap();
+            loop {
+                if let Some(job) = queue.jobs.pop_front() {
+                    queue.active += 1;
+                    break job;
+                }
+                // Nothing is queued, and nothing being worked on can queue anything more.
+                if queue.active == 0 {
+                    return;
+                }
+                queue = changed.wait(queue).unwrap();
+            }
+        };
+
+        let _active = Active { queue, changed };
+        let discovered = files.prefetch(job.owns_directory, &job.path, job.cfg);
+
+        let mut queue = queue.lock().unwrap();
+        for (owns_directory, path) in discovered {
+            if queue.seen.insert((path.clone(), job.cfg_id)) {
+                queue.jobs.push_back(Job {
+                    cfg: job.cfg,
+                    cfg_id: job.cfg_id,
+                    owns_directory,
+                    path,
+                });
+            }
+        }
+    }
+}
+
+/// Marks the end of a job when dropped, even if working on it panicked, so that the other
+/// workers don't wait for it forever.
+struct Active<'q, 'a> {
+    queue: &'q Mutex<Queue<'a>>,
+    changed: &'q Condvar,
+}
+
+impl Drop for Active<'_, '_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.active -= 1;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseMode;
+    use crate::cfg::CfgOption;
+    use std::fs;
+
+    #[test]
+    fn test_parse_all_per_cfg() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dunce::canonicalize(dir.path()).unwrap();
+        let lib = root.join("lib.rs");
+        fs::write(&lib, "#[cfg(unix)]\nmod unix;").unwrap();
+        fs::write(root.join("unix.rs"), "").unwrap();
+
+        // The same root is reached by two targets, only one of which enables `unix`.
+        let files = ParsedFiles::new(ParseMode::Fast);
+        let empty = CfgSet::new();
+        let unix = [CfgOption::Name(String::from("unix"))]
+            .into_iter()
+            .collect::<CfgSet>();
+        parse_all(&files, &[(&lib, Some(&empty)), (&lib, Some(&unix))], 2);
+        assert_eq!(files.len(), 2);
+    }
+}
@@ -1,69 +1,521 @@
-use crate::Error;
-use std::collections::HashSet;
+use crate::cache::DiskCache;
+use crate::cfg::{CfgExpr, CfgSet};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::tree::{Declaration, ModuleId, ModuleSource};
+use crate::{Error, ModuleCycle, Options, ParseMode, TargetInputs, scanner};
+use proc_macro2::TokenStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
 use syn::visit::Visit;
-use syn::{Expr, ExprLit, ItemMod, Lit, Meta};
+use syn::{Expr, ExprLit, ExprMacro, ItemMod, Lit, Macro, Meta, Token};
 
-#[derive(Default, Debug)]
-struct ModVisitor {
-    modules: Vec<Module>,
-    stack: Vec<PathComponent>,
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub(crate) struct ModVisitor {
+    pub(crate) modules: Vec<Module>,
+    pub(crate) includes: Vec<Include>,
+    /// The syntax error which stopped the file from being parsed, if its declarations were
+    /// instead found by scanning its tokens.
+    pub(crate) recovered_from: Option<SyntaxError>,
+    #[serde(skip)]
+    pub(crate) stack: Vec<PathComponent>,
 }
 
-impl<'ast> Visit<'ast> for ModVisitor {
-    fn visit_item_mod(&mut self, item: &'ast ItemMod) {
-        // Parse any #[path = "bla.rs"] declaration.
-        let mut path = None;
-        for attr in &item.attrs {
-            let Meta::NameValue(meta) = &attr.meta else {
-                continue;
-            };
+/// A syntax error reported by syn.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SyntaxError {
+    span: Span,
+    message: String,
+}
 
-            let Some(attr_ident) = attr.path().get_ident() else {
-                continue;
-            };
-            if attr_ident != "path" {
-                continue;
+impl ModVisitor {
+    /// Read and parse the given file, returning the modules and includes it declares.
+    fn parse(path: &Path, mode: ParseMode) -> Result<Self, Error> {
+        let source =
+            fs::read_to_string(path).map_err(|e| Error::FileError(path.to_path_buf(), e))?;
+
+        // The scanner can't make sense of files which can't be tokenized, in which case syn
+        // reports what is wrong with them.
+        if mode == ParseMode::Fast
+            && let Some(visitor) = scanner::scan(&source)
+        {
+            return Ok(visitor);
+        }
+
+        let file = match syn::parse_file(&source) {
+            Ok(file) => file,
+            // Files using syntax which syn doesn't support, such as nightly-only features, can
+            // usually still be scanned.
+            Err(error) if mode == ParseMode::Full => {
+                let mut visitor =
+                    scanner::scan(&source).ok_or_else(|| Error::parse(path, &error))?;
+                visitor.recovered_from = Some(SyntaxError {
+                    span: Span::from(error.span()),
+                    message: error.to_string(),
+                });
+                return Ok(visitor);
             }
+            Err(error) => return Err(Error::parse(path, &error)),
+        };
+        let mut visitor = ModVisitor::default();
+        visitor.visit_file(&file);
+        Ok(visitor)
+    }
+
+    /// Return the declarations of this visitor as if they were written within the given inline
+    /// modules, as is the case for a file pulled in using `include!`.
+    fn nested(&self, parts: &[PathComponent]) -> Self {
+        let prefixed = |own: &[PathComponent]| [parts, own].concat();
+        ModVisitor {
+            modules: self
+                .modules
+                .iter()
+                .map(|module| Module {
+                    parts: prefixed(&module.parts),
+                    outer: parts.len() + module.outer,
+                    ..module.clone()
+                })
+                .collect(),
+            includes: self
+                .includes
+                .iter()
+                .map(|include| Include {
+                    parts: prefixed(&include.parts),
+                    ..include.clone()
+                })
+                .collect(),
+            recovered_from: self.recovered_from.clone(),
+            stack: Vec::new(),
+        }
+    }
+}
+
+/// Source files which have been parsed, shared between the traversals of several targets.
+#[derive(Debug, Default)]
+pub(crate) struct ParsedFiles {
+    files: Mutex<HashMap<PathBuf, Arc<ModVisitor>>>,
+    /// Files parsed by previous runs, if enabled.
+    disk: Option<DiskCache>,
+    mode: ParseMode,
+}
 
-            let Expr::Lit(ExprLit {
-                lit: Lit::Str(lit), ..
-            }) = &meta.value
-            else {
+impl ParsedFiles {
+    /// Create an empty set of parsed files, which are parsed using the given mode.
+    pub(crate) fn new(mode: ParseMode) -> Self {
+        ParsedFiles {
+            files: Mutex::default(),
+            disk: None,
+            mode,
+        }
+    }
+
+    /// Create an empty set of parsed files, backed by the on-disk cache in the given directory.
+    pub(crate) fn with_disk_cache(dir: &Path, mode: ParseMode) -> Self {
+        ParsedFiles {
+            files: Mutex::default(),
+            disk: Some(DiskCache::load(dir)),
+            mode,
+        }
+    }
+
+    /// Write any newly parsed files to the on-disk cache, if enabled.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        match &self.disk {
+            Some(disk) => disk.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the number of files which have been parsed.
+    pub(crate) fn len(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    /// Forget the given file, or every file within the given directory.
+    pub(crate) fn forget(&self, path: &Path) {
+        self.files
+            .lock()
+            .unwrap()
+            .retain(|file, _| !file.starts_with(path));
+    }
+
+    /// Return the declarations in the given file, parsing it if it hasn't been already.
+    ///
+    /// Files which can't be read or parsed aren't remembered, so the error is returned each time.
+    fn get(&self, path: &Path) -> Result<Arc<ModVisitor>, Error> {
+        if let Some(visitor) = self.files.lock().unwrap().get(path) {
+            return Ok(Arc::clone(visitor));
+        }
+
+        // Parse without holding the lock, so that other threads can parse at the same time.
+        let visitor = match &self.disk {
+            Some(disk) => {
+                let metadata =
+                    fs::metadata(path).map_err(|e| Error::FileError(path.to_path_buf(), e))?;
+                match disk.get(path, &metadata, self.mode) {
+                    Some(visitor) => visitor,
+                    None => {
+                        let visitor = Arc::new(ModVisitor::parse(path, self.mode)?);
+                        disk.insert(path, &metadata, self.mode, Arc::clone(&visitor));
+                        visitor
+                    }
+                }
+            }
+            None => Arc::new(ModVisitor::parse(path, self.mode)?),
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::clone(&visitor));
+        Ok(visitor)
+    }
+
+    /// Parse the given file ahead of a traversal, returning the files it likely leads to.
+    ///
+    /// Each file is returned together with whether it owns its directory, as described for
+    /// `owns_directory` in [`Module::resolve`]. Problems are ignored here, and reported by the
+    /// traversal itself.
+    pub(crate) fn prefetch(
+        &self,
+        owns_directory: bool,
+        path: &Path,
+        cfg: Option<&CfgSet>,
+    ) -> Vec<(bool, PathBuf)> {
+        let Ok(visitor) = self.get(path) else {
+            return Vec::new();
+        };
+
+        let mut discovered = Vec::new();
+        for module in &visitor.modules {
+            if module.inline || cfg.is_some_and(|cfg| !module.is_active(cfg)) {
                 continue;
-            };
-            path = Some(lit.value());
-            break;
+            }
+            if let Ok(resolution) = module.resolve(owns_directory, path, cfg) {
+                for resolved in resolution.paths {
+                    discovered.push((false, resolved.path));
+                }
+            }
+        }
+        for include in &visitor.includes {
+            if include.kind != IncludeKind::Source
+                || cfg.is_some_and(|cfg| !is_active(&include.parts, cfg))
+            {
+                continue;
+            }
+            if let Ok(included_path) = include.resolve(path) {
+                discovered.push((true, included_path));
+            }
         }
 
-        self.stack.push(PathComponent {
-            name: item.ident.to_string(),
-            path,
-        });
+        for (_, path) in &mut discovered {
+            *path = dunce::canonicalize(&*path).unwrap_or(path.clone());
+        }
+        discovered
+    }
+}
 
-        // AFAIK mod foobar {} blocks don't contribute a file
-        if item.content.is_none() {
-            self.modules.push(Module {
-                parts: self.stack.clone(),
-            });
+impl<'ast> Visit<'ast> for ModVisitor {
+    fn visit_item_mod(&mut self, item: &'ast ItemMod) {
+        // Parse any #[path = "bla.rs"], #[cfg(...)] and #[cfg_attr(...)] attributes.
+        let mut component = PathComponent::new(item.ident.to_string());
+        for attr in &item.attrs {
+            component.parse_attribute(&attr.meta, None);
         }
+        self.stack.push(component);
+
+        // AFAIK mod foobar {} blocks don't contribute a file, but we record them so that they
+        // appear in the module tree.
+        let end = item.semi.map_or(item.ident.span(), |semi| semi.span);
+        self.modules.push(Module {
+            parts: self.stack.clone(),
+            span: Span::between(item.mod_token.span, end),
+            inline: item.content.is_some(),
+            outer: 0,
+        });
 
         syn::visit::visit_item_mod(self, item);
         self.stack.pop().expect("should be balanced");
     }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        if let Some(kind) = IncludeKind::from_macro(mac) {
+            // Invocations whose argument isn't known until compile time, such as
+            // include!(concat!(env!("OUT_DIR"), "/foo.rs")), are skipped.
+            if let Some(path) = parse_include_argument(mac) {
+                self.includes.push(Include {
+                    kind,
+                    path,
+                    parts: self.stack.clone(),
+                    span: Span::from(mac.span()),
+                });
+            }
+        }
+
+        syn::visit::visit_macro(self, mac);
+    }
 }
 
-#[derive(Clone, Debug)]
-struct PathComponent {
-    name: String,
+/// The kinds of macro which pull the contents of another file into the crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum IncludeKind {
+    /// `include!`, which parses the file as Rust source.
+    Source,
+    /// `include_str!` or `include_bytes!`, which embed the file verbatim.
+    Asset,
+}
+
+impl IncludeKind {
+    fn from_macro(mac: &Macro) -> Option<Self> {
+        let segments = mac
+            .path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect::<Vec<_>>();
+        IncludeKind::from_path(&segments)
+    }
 
-    /// An optional path override, set using the #[path = "..."] attribute.
-    path: Option<String>,
+    /// Return the kind of macro with the given path, if it is an `include!`-like macro.
+    pub(crate) fn from_path(segments: &[impl AsRef<str>]) -> Option<Self> {
+        // Accept both `include!` and `std::include!` (or `core::include!`).
+        let (last, prefix) = segments.split_last()?;
+        if prefix.len() > 1
+            || prefix
+                .first()
+                .is_some_and(|first| !matches!(first.as_ref(), "std" | "core"))
+        {
+            return None;
+        }
+
+        match last.as_ref() {
+            "include" => Some(IncludeKind::Source),
+            "include_str" | "include_bytes" => Some(IncludeKind::Asset),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug)]
-struct Module {
+/// Return the path passed to an `include!`-like macro, if it is a string literal or a `concat!`
+/// of literals.
+fn parse_include_argument(mac: &Macro) -> Option<String> {
+    parse_include_tokens(mac.tokens.clone())
+}
+
+/// Like [`parse_include_argument`], given the tokens between the macro's delimiters.
+pub(crate) fn parse_include_tokens(tokens: TokenStream) -> Option<String> {
+    let args = Punctuated::<Expr, Token![,]>::parse_terminated
+        .parse2(tokens)
+        .ok()?;
+    let [arg] = args.iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    evaluate_string_expr(arg)
+}
+
+fn evaluate_string_expr(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Lit(ExprLit { lit, .. }) => match lit {
+            Lit::Str(lit) => Some(lit.value()),
+            Lit::Char(lit) => Some(lit.value().to_string()),
+            Lit::Int(lit) => Some(lit.base10_digits().to_string()),
+            Lit::Float(lit) => Some(lit.base10_digits().to_string()),
+            Lit::Bool(lit) => Some(lit.value.to_string()),
+            _ => None,
+        },
+        Expr::Macro(ExprMacro { mac, .. }) if mac.path.is_ident("concat") => mac
+            .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+            .ok()?
+            .iter()
+            .map(evaluate_string_expr)
+            .collect(),
+        Expr::Group(group) => evaluate_string_expr(&group.expr),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Include {
+    pub(crate) kind: IncludeKind,
+
+    /// The path passed to the macro, which is relative to the file containing the invocation.
+    pub(crate) path: String,
+
+    /// The inline modules enclosing the invocation.
+    pub(crate) parts: Vec<PathComponent>,
+
+    /// The location of the invocation.
+    pub(crate) span: Span,
+}
+
+impl Include {
+    /// Return a description of this invocation, such as `include!("a.rs")`.
+    fn describe(&self) -> String {
+        format!("include!({:?})", self.path)
+    }
+
+    /// Return the file this invocation includes.
+    fn resolve(&self, source_file_path: &Path) -> Result<PathBuf, Error> {
+        let source_file_directory = source_file_path.parent().ok_or(Error::NoParent)?;
+        let path = source_file_directory.join(&self.path);
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(Error::IncludeNotFound {
+                file: source_file_path.to_path_buf(),
+                span: self.span,
+                path,
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PathComponent {
+    pub(crate) name: String,
+
+    /// Path overrides set using #[path = "..."] attributes, in declaration order.
+    paths: Vec<PathOverride>,
+
+    /// The #[cfg(...)] predicates on this module declaration, all of which must hold.
+    cfgs: Vec<CfgExpr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct PathOverride {
+    path: String,
+
+    /// The predicate of the enclosing #[cfg_attr(...)], or `None` if the override is unconditional.
+    cfg: Option<CfgExpr>,
+}
+
+/// A choice of path override for a single path component.
+#[derive(Clone, Copy, Debug)]
+struct PathChoice<'a> {
+    /// The path override to use, or `None` to resolve using the module name.
+    path: Option<&'a str>,
+
+    /// Whether it is acceptable for this choice not to resolve to a file.
+    optional: bool,
+}
+
+impl PathComponent {
+    pub(crate) fn new(name: String) -> Self {
+        PathComponent {
+            name,
+            paths: Vec::new(),
+            cfgs: Vec::new(),
+        }
+    }
+
+    /// Record the given attribute if it affects module resolution.
+    ///
+    /// The `condition` is the predicate of any enclosing `#[cfg_attr(...)]`. Attributes we can't
+    /// parse are ignored, so that we never drop a file the compiler might use.
+    pub(crate) fn parse_attribute(&mut self, meta: &Meta, condition: Option<&CfgExpr>) {
+        match meta {
+            Meta::NameValue(meta) if meta.path.is_ident("path") => {
+                let Expr::Lit(ExprLit {
+                    lit: Lit::Str(lit), ..
+                }) = &meta.value
+                else {
+                    return;
+                };
+                self.paths.push(PathOverride {
+                    path: lit.value(),
+                    cfg: condition.cloned(),
+                });
+            }
+            Meta::List(list) if list.path.is_ident("cfg") => {
+                let Ok(expr) = list
+                    .parse_args::<Meta>()
+                    .and_then(|meta| CfgExpr::from_meta(&meta))
+                else {
+                    return;
+                };
+                // #[cfg_attr(a, cfg(b))] only requires `b` to hold when `a` does.
+                self.cfgs.push(match condition {
+                    Some(condition) => {
+                        CfgExpr::Any(vec![CfgExpr::Not(Box::new(condition.clone())), expr])
+                    }
+                    None => expr,
+                });
+            }
+            Meta::List(list) if list.path.is_ident("cfg_attr") => {
+                let Ok(args) =
+                    list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                else {
+                    return;
+                };
+                let mut args = args.into_iter();
+                let Some(Ok(predicate)) = args.next().map(|meta| CfgExpr::from_meta(&meta)) else {
+                    return;
+                };
+                let predicate = match condition {
+                    Some(condition) => CfgExpr::All(vec![condition.clone(), predicate]),
+                    None => predicate,
+                };
+                for meta in args {
+                    self.parse_attribute(&meta, Some(&predicate));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Return true if a path override could apply to this component.
+    fn has_path_attribute(&self, cfg: Option<&CfgSet>) -> bool {
+        self.path_choices(cfg)
+            .iter()
+            .any(|choice| choice.path.is_some())
+    }
+
+    /// Return the path overrides which could apply to this component.
+    ///
+    /// With a cfg set, this is the first override whose predicate holds. Without one, it is every
+    /// override up to the first unconditional one, together with the module name if all of them
+    /// are conditional.
+    fn path_choices(&self, cfg: Option<&CfgSet>) -> Vec<PathChoice<'_>> {
+        if let Some(cfg) = cfg {
+            let path = self
+                .paths
+                .iter()
+                .find(|path| path.cfg.as_ref().is_none_or(|expr| cfg.is_active(expr)))
+                .map(|path| path.path.as_str());
+            return vec![PathChoice {
+                path,
+                optional: false,
+            }];
+        }
+
+        let mut choices = Vec::new();
+        for path in &self.paths {
+            choices.push(PathChoice {
+                path: Some(&path.path),
+                optional: false,
+            });
+            if path.cfg.is_none() {
+                return choices;
+            }
+        }
+
+        // Every override is conditional, so the module may also be found using its name.
+        choices.push(PathChoice {
+            path: None,
+            optional: !self.paths.is_empty(),
+        });
+        choices
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Module {
     /// The collection of path components that make up this module definition.
     ///
     /// The source code:
@@ -75,26 +527,162 @@ struct Module {
     /// }
     ///
     /// would give rise to a single Module, having two parts.
-    parts: Vec<PathComponent>,
+    pub(crate) parts: Vec<PathComponent>,
+
+    /// The location of the `mod b_mod;` declaration.
+    pub(crate) span: Span,
+
+    /// Whether this is an inline module, such as `mod a_mod { ... }`, which has no file.
+    pub(crate) inline: bool,
+
+    /// The number of leading parts which enclose the `include!` that pulled in this
+    /// declaration. They belong to the module's name, but not to the path of its file, as
+    /// included files resolve modules from their own directory.
+    #[serde(default)]
+    pub(crate) outer: usize,
+}
+
+/// The source files corresponding to a module.
+#[derive(Debug, Default)]
+struct Resolution {
+    paths: Vec<ResolvedPath>,
+
+    /// An `Error::AmbiguousModule` for each path which could have been resolved in two ways.
+    ambiguities: Vec<Error>,
+}
+
+#[derive(Debug)]
+struct ResolvedPath {
+    path: PathBuf,
+
+    /// Whether the path was chosen using a #[path] attribute on any of the module's parts.
+    has_path_attribute: bool,
+}
+
+/// Return true if every one of the given path components is enabled under the given cfg set.
+fn is_active(parts: &[PathComponent], cfg: &CfgSet) -> bool {
+    parts
+        .iter()
+        .flat_map(|part| &part.cfgs)
+        .all(|expr| cfg.is_active(expr))
+}
+
+/// Return true if any of the given path components has a `#[cfg(...)]` predicate.
+fn is_cfg_gated(parts: &[PathComponent]) -> bool {
+    parts.iter().any(|part| !part.cfgs.is_empty())
 }
 
 impl Module {
-    /// Return the source file corresponding to this module.
-    fn resolve(self, root_path: &Path, source_file_path: &Path) -> Result<PathBuf, Error> {
-        assert!(!self.parts.is_empty());
+    /// Return true if every part of this module definition is enabled under the given cfg set.
+    fn is_active(&self, cfg: &CfgSet) -> bool {
+        is_active(&self.parts, cfg)
+    }
+
+    /// Return the name of this module relative to the declaring file, such as `a::b`.
+    fn name(&self) -> String {
+        let names = self.parts.iter().map(|part| part.name.as_str());
+        names.collect::<Vec<_>>().join("::")
+    }
+
+    /// Return a description of this module definition, such as `mod a::b`.
+    fn describe(&self) -> String {
+        format!("mod {}", self.name())
+    }
+
+    /// Return the source files corresponding to this module.
+    ///
+    /// With a cfg set this is the single file selected by any `#[cfg_attr(..., path = "...")]`
+    /// attributes. Without one, every file which could be selected is returned.
+    ///
+    /// Module paths are resolved from the directory of `source_file_path` if `owns_directory`
+    /// is set, as for a crate root, a `mod.rs` file or a file pulled in using `include!`, and
+    /// from the directory named after it otherwise.
+    fn resolve(
+        &self,
+        owns_directory: bool,
+        source_file_path: &Path,
+        cfg: Option<&CfgSet>,
+    ) -> Result<Resolution, Error> {
+        assert!(self.parts.len() > self.outer);
 
+        // Take every combination of path choices across the components.
+        let mut combinations: Vec<Vec<PathChoice>> = vec![Vec::new()];
+        for part in &self.parts[self.outer..] {
+            let choices = part.path_choices(cfg);
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    choices.iter().map(move |choice| {
+                        let mut combination = combination.clone();
+                        combination.push(*choice);
+                        combination
+                    })
+                })
+                .collect();
+        }
+
+        let mut resolution = Resolution::default();
+        let mut searched = Vec::new();
+        let mut missing_required = false;
+        for combination in combinations {
+            let candidates = self.candidates(&combination, owns_directory, source_file_path)?;
+            let mut existing = candidates.iter().filter(|candidate| candidate.exists());
+            match existing.next() {
+                Some(path) => {
+                    // rustc rejects modules having both {name}.rs and {name}/mod.rs (E0761).
+                    if let Some(other) = existing.next() {
+                        resolution.ambiguities.push(Error::AmbiguousModule {
+                            file: source_file_path.to_path_buf(),
+                            span: self.span,
+                            name: self.name(),
+                            candidates: vec![path.clone(), other.clone()],
+                        });
+                    }
+                    resolution.paths.push(ResolvedPath {
+                        path: path.clone(),
+                        has_path_attribute: combination.iter().any(|c| c.path.is_some()),
+                    });
+                }
+                None => missing_required |= !combination.iter().any(|c| c.optional),
+            }
+            searched.extend(candidates);
+        }
+
+        if missing_required || resolution.paths.is_empty() {
+            Err(Error::ModuleNotFound {
+                file: source_file_path.to_path_buf(),
+                span: self.span,
+                name: self.name(),
+                candidates: searched,
+            })
+        } else {
+            Ok(resolution)
+        }
+    }
+
+    /// Return the paths which may contain this module given a path choice for each part after
+    /// the outer ones, in the order they should be checked.
+    fn candidates(
+        &self,
+        choices: &[PathChoice],
+        owns_directory: bool,
+        source_file_path: &Path,
+    ) -> Result<Vec<PathBuf>, Error> {
         let source_file_directory = source_file_path
             .parent()
             .ok_or(Error::NoParent)?
             .to_path_buf();
 
-        let mut base_resolution_path = resolve_base_resolution_path(root_path, source_file_path)?;
-        let (final_part, head) = self.parts.split_last().unwrap();
+        let mut base_resolution_path =
+            resolve_base_resolution_path(owns_directory, source_file_path)?;
+        let (final_part, head) = self.parts[self.outer..].split_last().unwrap();
+        let (final_choice, head_choices) = choices.split_last().unwrap();
 
         // Handle parent module paths
-        for (i, component) in head.iter().enumerate() {
-            if let Some(path) = component.path.as_deref() {
-                assert!(!path.ends_with(".rs")); // should be a _folder_
+        for (i, (component, choice)) in head.iter().zip(head_choices).enumerate() {
+            // The path of an inline module names a folder, even if it ends with `.rs`. It is
+            // pushed like any other, so a missing file is reported as a missing module.
+            if let Some(path) = choice.path {
                 if i == 0 {
                     // Special-case: A top-level module definition having a path attribute
                     // is always resolved relative to the source file.
@@ -108,43 +696,30 @@ impl Module {
         }
 
         // Handle the actual module definition path
-        if let Some(path) = final_part.path.as_deref() {
+        if let Some(path) = final_choice.path {
             if head.is_empty() {
                 // There are no parent modules, so this is actually a top-level module definition.
                 base_resolution_path = source_file_directory.join(path);
             } else {
                 base_resolution_path.push(path);
             }
-            return if base_resolution_path.exists() {
-                Ok(base_resolution_path)
-            } else {
-                Err(Error::ModuleNotFound)
-            };
-        }
-
-        // Look for a new-style module {name}.rs
-        base_resolution_path.push(format!("{}.rs", final_part.name));
-        if base_resolution_path.exists() {
-            return Ok(base_resolution_path);
+            return Ok(vec![base_resolution_path]);
         }
 
-        // Look for an old-style module {name}/mod.rs
-        base_resolution_path.pop();
-        base_resolution_path.extend([&final_part.name, "mod.rs"]);
-        if base_resolution_path.exists() {
-            return Ok(base_resolution_path);
-        }
-
-        Err(Error::ModuleNotFound)
+        // Look for a new-style module {name}.rs, then an old-style module {name}/mod.rs
+        Ok(vec![
+            base_resolution_path.join(format!("{}.rs", final_part.name)),
+            base_resolution_path.join(&final_part.name).join("mod.rs"),
+        ])
     }
 }
 
 fn resolve_base_resolution_path(
-    root_path: &Path,
+    owns_directory: bool,
     source_file_path: &Path,
 ) -> Result<PathBuf, Error> {
     let base_name = source_file_path.file_stem().ok_or(Error::NoStem)?;
-    let is_mod_rs = (root_path == source_file_path) || base_name == "mod";
+    let is_mod_rs = owns_directory || base_name == "mod";
 
     let mut source_file_directory = source_file_path
         .parent()
@@ -160,38 +735,273 @@ fn resolve_base_resolution_path(
     Ok(source_file_directory)
 }
 
+/// Collect the given source file and all files reachable from it into `acc`.
+///
+/// If a cfg set is given, modules whose `#[cfg(...)]` predicates don't hold are skipped.
 pub fn extract_crate_files(
     root_path: &Path,
     path: &Path,
-    acc: &mut HashSet<PathBuf>,
+    options: &Options,
+    acc: &mut TargetInputs,
 ) -> Result<(), Error> {
-    acc.insert(path.to_path_buf());
-    let source = fs::read_to_string(path).map_err(|e| Error::FileError(path.to_path_buf(), e))?;
+    extract_crate_files_with(
+        root_path,
+        path,
+        options,
+        acc,
+        &ParsedFiles::new(options.parse_mode),
+    )
+}
 
-    // Extract all the mod definitions in the given file
-    let file = syn::parse_file(&source)?;
-    let mut visitor = ModVisitor::default();
-    visitor.visit_file(&file);
+/// Like [`extract_crate_files`], but reusing files which have already been parsed.
+pub(crate) fn extract_crate_files_with(
+    root_path: &Path,
+    path: &Path,
+    options: &Options,
+    acc: &mut TargetInputs,
+    files: &ParsedFiles,
+) -> Result<(), Error> {
+    let mut traversal = Traversal {
+        root_path,
+        files,
+        cfg: options.cfg.as_ref(),
+        keep_going: options.keep_going,
+        strict: options.strict,
+        acc,
+        stack: Vec::new(),
+    };
+    let root = traversal.acc.modules.root();
+    traversal.extract_file(path, root)
+}
+
+struct Traversal<'a> {
+    root_path: &'a Path,
+    files: &'a ParsedFiles,
+    cfg: Option<&'a CfgSet>,
+    keep_going: bool,
+    strict: bool,
+    acc: &'a mut TargetInputs,
 
-    for module in visitor.modules {
-        let module_path = module.resolve(root_path, path)?;
-        let canonical_module_path = dunce::canonicalize(&module_path).unwrap_or(module_path);
-        extract_crate_files(root_path, &canonical_module_path, acc)?;
-        acc.insert(canonical_module_path);
+    /// The files currently being visited, each with the declaration being followed out of it.
+    stack: Vec<(PathBuf, String)>,
+}
+
+impl Traversal<'_> {
+    /// Collect the given module file and all files reachable from it.
+    ///
+    /// The file is the source of the `module` node in the module tree.
+    fn extract_file(&mut self, path: &Path, module: ModuleId) -> Result<(), Error> {
+        if let Err(e) = self.check_for_cycle(path) {
+            return self.report(e);
+        }
+        self.acc.sources.insert(path.to_path_buf());
+
+        // Extract all the mod definitions in the given file
+        let visitor = match self.files.get(path) {
+            Ok(visitor) => visitor,
+            Err(e) => return self.report(e),
+        };
+        if let Some(error) = &visitor.recovered_from {
+            // In strict mode the declarations recovered by scanning the file aren't used.
+            if self.strict {
+                return self.report(Error::ParseError {
+                    file: path.to_path_buf(),
+                    span: error.span,
+                    message: error.message.clone(),
+                });
+            }
+            self.acc.recovered.insert(path.to_path_buf());
+            self.warn(Error::ParsedWithRecovery {
+                file: path.to_path_buf(),
+                span: error.span,
+                message: error.message.clone(),
+            })?;
+        }
+        self.extract_declared_files(path == self.root_path, path, &visitor, module)
     }
 
-    Ok(())
+    /// Collect the files declared by a visited source file.
+    ///
+    /// Modules and includes are both resolved relative to `source_file_path`. Modules are
+    /// resolved from its own directory if `owns_directory` is set, which is the case for the
+    /// crate root and for files pulled in with `include!`, as rustc does.
+    ///
+    /// Declared modules are added to the module tree as descendants of `parent`.
+    fn extract_declared_files(
+        &mut self,
+        owns_directory: bool,
+        source_file_path: &Path,
+        visitor: &ModVisitor,
+        parent: ModuleId,
+    ) -> Result<(), Error> {
+        for module in &visitor.modules {
+            if self.cfg.is_some_and(|cfg| !module.is_active(cfg)) {
+                continue;
+            }
+
+            let declaration = Declaration {
+                file: source_file_path.to_path_buf(),
+                span: module.span,
+            };
+            if module.inline {
+                self.inline_module_node(parent, &module.parts, declaration);
+                continue;
+            }
+
+            let resolution = match module.resolve(owns_directory, source_file_path, self.cfg) {
+                Ok(resolution) => resolution,
+                Err(e) => {
+                    self.report(e)?;
+                    continue;
+                }
+            };
+            for ambiguity in resolution.ambiguities {
+                self.warn(ambiguity)?;
+            }
+            let (name, head) = module.parts.split_last().unwrap();
+            let module_parent = self.inline_module_node(parent, head, declaration.clone());
+            for resolved in resolution.paths {
+                let canonical_path = dunce::canonicalize(&resolved.path).unwrap_or(resolved.path);
+                let node = self.acc.modules.add(
+                    module_parent,
+                    &name.name,
+                    ModuleSource::File(canonical_path.clone()),
+                    resolved.has_path_attribute,
+                    name.cfgs.clone(),
+                    declaration.clone(),
+                );
+
+                self.stack
+                    .push((source_file_path.to_path_buf(), module.describe()));
+                self.extract_file(&canonical_path, node)?;
+                self.stack.pop();
+                self.acc.sources.insert(canonical_path);
+            }
+        }
+
+        for include in &visitor.includes {
+            if self.cfg.is_some_and(|cfg| !is_active(&include.parts, cfg)) {
+                continue;
+            }
+
+            let resolved_path = match include.resolve(source_file_path) {
+                Ok(resolved_path) => resolved_path,
+                // Without a cfg set, an include within a cfg-gated module, such as test data
+                // included by `#[cfg(test)] mod tests`, may never be compiled.
+                Err(e) if self.cfg.is_none() && is_cfg_gated(&include.parts) => {
+                    self.warn(e)?;
+                    continue;
+                }
+                Err(e) => {
+                    self.report(e)?;
+                    continue;
+                }
+            };
+            let canonical_path = dunce::canonicalize(&resolved_path).unwrap_or(resolved_path);
+            let declaration = Declaration {
+                file: source_file_path.to_path_buf(),
+                span: include.span,
+            };
+            let module = self.inline_module_node(parent, &include.parts, declaration);
+            self.acc.modules.include(module, canonical_path.clone());
+            match include.kind {
+                IncludeKind::Asset => {
+                    self.acc.assets.insert(canonical_path);
+                }
+                IncludeKind::Source => {
+                    if let Err(e) = self.check_for_cycle(&canonical_path) {
+                        self.report(e)?;
+                        continue;
+                    }
+                    self.acc.sources.insert(canonical_path.clone());
+                    // Included files needn't be a sequence of items (e.g. they may be a single
+                    // expression), so they aren't expected to parse as a file, and declarations
+                    // recovered by scanning them are used without a warning. Files which can't
+                    // even be tokenized are reported, as any modules they declare are lost.
+                    let included_visitor = match self.files.get(&canonical_path) {
+                        Ok(visitor) => visitor.nested(&include.parts),
+                        Err(e) => {
+                            self.report(e)?;
+                            continue;
+                        }
+                    };
+
+                    self.stack
+                        .push((source_file_path.to_path_buf(), include.describe()));
+                    self.extract_declared_files(true, &canonical_path, &included_visitor, parent)?;
+                    self.stack.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the node for the inline module with the given parts, relative to `parent`.
+    fn inline_module_node(
+        &mut self,
+        parent: ModuleId,
+        parts: &[PathComponent],
+        declaration: Declaration,
+    ) -> ModuleId {
+        let mut node = parent;
+        let mut has_path_attribute = false;
+        for part in parts {
+            has_path_attribute |= part.has_path_attribute(self.cfg);
+            node = self.acc.modules.inline(
+                node,
+                &part.name,
+                has_path_attribute,
+                &part.cfgs,
+                declaration.clone(),
+            );
+        }
+        node
+    }
+
+    /// Return an error if the given file is already being visited.
+    fn check_for_cycle(&self, path: &Path) -> Result<(), Error> {
+        let Some(start) = self.stack.iter().position(|(file, _)| file == path) else {
+            return Ok(());
+        };
+
+        Err(Error::ModuleCycle(ModuleCycle {
+            steps: self.stack[start..].to_vec(),
+        }))
+    }
+
+    /// Record the given error as a diagnostic if we're keeping going, or return it otherwise.
+    fn report(&mut self, error: Error) -> Result<(), Error> {
+        if self.keep_going {
+            self.acc.diagnostics.push(Diagnostic::error(error));
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Record the given problem as a warning, or report it as an error in strict mode.
+    fn warn(&mut self, error: Error) -> Result<(), Error> {
+        if self.strict {
+            self.report(error)
+        } else {
+            self.acc.diagnostics.push(Diagnostic::warning(error));
+            Ok(())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cfg::CfgOption;
 
     fn assert_unique_module_parts(source: &str, parts: Vec<(&str, Option<&str>)>) {
         let file = syn::parse_file(source).unwrap();
         let mut visitor = ModVisitor::default();
         visitor.visit_file(&file);
 
+        visitor.modules.retain(|module| !module.inline);
         assert_eq!(visitor.modules.len(), 1);
 
         let module = visitor.modules.pop().unwrap();
@@ -200,7 +1010,8 @@ mod tests {
         for (path_component, (expected_name, expected_path)) in module.parts.into_iter().zip(parts)
         {
             assert_eq!(path_component.name, expected_name);
-            assert_eq!(path_component.path, expected_path.map(String::from));
+            let path = path_component.paths.first().map(|path| path.path.as_str());
+            assert_eq!(path, expected_path);
         }
     }
 
@@ -260,6 +1071,131 @@ mod tests {
         assert_unique_module_parts(source, vec![("intern", None)]);
     }
 
+    #[test]
+    fn test_cfg_attribute_parsing() {
+        let source = r#"
+        #[cfg(unix)]
+        mod a {
+            #[cfg(feature = "tls")]
+            #[cfg(not(test))]
+            mod b;
+        }
+        "#;
+
+        let file = syn::parse_file(source).unwrap();
+        let mut visitor = ModVisitor::default();
+        visitor.visit_file(&file);
+
+        let module = visitor.modules.pop().unwrap();
+        assert_eq!(module.parts[0].cfgs, vec![CfgExpr::parse("unix").unwrap()]);
+        assert_eq!(
+            module.parts[1].cfgs,
+            vec![
+                CfgExpr::parse(r#"feature = "tls""#).unwrap(),
+                CfgExpr::parse("not(test)").unwrap()
+            ]
+        );
+
+        let unix_tls = [
+            CfgOption::Name(String::from("unix")),
+            CfgOption::feature("tls"),
+        ];
+        assert!(module.is_active(&unix_tls.iter().cloned().collect()));
+        assert!(!module.is_active(&unix_tls[..1].iter().cloned().collect()));
+        assert!(!module.is_active(&unix_tls[1..].iter().cloned().collect()));
+    }
+
+    #[test]
+    fn test_cfg_attr_path_parsing() {
+        let source = r#"
+        #[cfg_attr(unix, path = "unix.rs")]
+        #[cfg_attr(windows, allow(dead_code), path = "windows.rs")]
+        #[cfg_attr(feature = "a", cfg_attr(feature = "b", path = "ab.rs"))]
+        #[cfg_attr(feature = "c", cfg(test))]
+        mod sys;
+        "#;
+
+        let file = syn::parse_file(source).unwrap();
+        let mut visitor = ModVisitor::default();
+        visitor.visit_file(&file);
+
+        let module = visitor.modules.pop().unwrap();
+        let expr = |input: &str| CfgExpr::parse(input).unwrap();
+        assert_eq!(
+            module.parts[0].paths,
+            vec![
+                PathOverride {
+                    path: String::from("unix.rs"),
+                    cfg: Some(expr("unix")),
+                },
+                PathOverride {
+                    path: String::from("windows.rs"),
+                    cfg: Some(expr("windows")),
+                },
+                PathOverride {
+                    path: String::from("ab.rs"),
+                    cfg: Some(expr(r#"all(feature = "a", feature = "b")"#)),
+                },
+            ]
+        );
+        assert_eq!(
+            module.parts[0].cfgs,
+            vec![expr(r#"any(not(feature = "c"), test)"#)]
+        );
+
+        let windows = [CfgOption::Name(String::from("windows"))];
+        let choices = module.parts[0].path_choices(Some(&windows.into_iter().collect()));
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].path, Some("windows.rs"));
+
+        let choices = module.parts[0].path_choices(Some(&CfgSet::new()));
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].path, None);
+
+        let choices = module.parts[0].path_choices(None);
+        let paths = choices.iter().map(|c| c.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![Some("unix.rs"), Some("windows.rs"), Some("ab.rs"), None]
+        );
+        assert!(choices[3].optional);
+    }
+
+    #[test]
+    fn test_include_parsing() {
+        let source = r#"
+        #![doc = include_str!("../README.md")]
+        include!("a.rs");
+        mod inline {
+            static B: &[u8] = std::include_bytes!(concat!("b", 1, ".bin"));
+            fn f() {
+                let _ = include!(concat!(env!("OUT_DIR"), "/c.rs"));
+            }
+        }
+        "#;
+
+        let file = syn::parse_file(source).unwrap();
+        let mut visitor = ModVisitor::default();
+        visitor.visit_file(&file);
+
+        let includes = visitor
+            .includes
+            .iter()
+            .map(|include| {
+                let parts = include.parts.iter().map(|part| part.name.as_str());
+                (include.kind, include.path.as_str(), parts.collect())
+            })
+            .collect::<Vec<(_, _, Vec<_>)>>();
+        assert_eq!(
+            includes,
+            vec![
+                (IncludeKind::Asset, "../README.md", vec![]),
+                (IncludeKind::Source, "a.rs", vec![]),
+                (IncludeKind::Asset, "b1.bin", vec!["inline"]),
+            ]
+        );
+    }
+
     #[test]
     fn test_path_and_nested_path() {
         let source = r#"
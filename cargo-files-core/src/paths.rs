@@ -0,0 +1,90 @@
+//! Presenting the paths of files to users.
+//!
+//! Files are identified by absolute, canonicalized paths, which are unambiguous but long.
+//! [`PathStyle`] turns them into paths relative to some directory, such as the workspace root.
+
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+/// How paths are presented.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Paths are presented as they are, which for the files of a target is absolute.
+    #[default]
+    Absolute,
+    /// Paths are presented relative to the given absolute directory, going through `..` for
+    /// files outside of it.
+    RelativeTo(PathBuf),
+}
+
+impl PathStyle {
+    /// Present paths relative to the given directory, which is canonicalized so that it can be
+    /// compared with the paths of files.
+    pub fn relative_to(dir: &Path) -> Self {
+        PathStyle::RelativeTo(dunce::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()))
+    }
+
+    /// Return the path to present for the given path.
+    ///
+    /// Relative paths, and paths which can't be made relative to the directory (such as those
+    /// on another drive), are returned unchanged.
+    pub fn apply<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match self {
+            PathStyle::Absolute => Cow::Borrowed(path),
+            PathStyle::RelativeTo(base) => match relative_path(path, base) {
+                Some(relative) => Cow::Owned(relative),
+                None => Cow::Borrowed(path),
+            },
+        }
+    }
+}
+
+/// Return the path of `path` relative to the directory `base`, if both are absolute and share
+/// a root.
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || !base.is_absolute() {
+        return None;
+    }
+
+    let mut path = path.components().peekable();
+    let mut base = base.components().peekable();
+    match (path.peek(), base.peek()) {
+        (Some(Component::Prefix(a)), Some(Component::Prefix(b))) if a != b => return None,
+        _ => {}
+    }
+    while let (Some(a), Some(b)) = (path.peek(), base.peek()) {
+        if a != b {
+            break;
+        }
+        path.next();
+        base.next();
+    }
+
+    let mut relative = base.map(|_| Component::ParentDir).collect::<PathBuf>();
+    relative.extend(path);
+    if relative.as_os_str().is_empty() {
+        relative.push(Component::CurDir);
+    }
+    Some(relative)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let relative = |path: &str, base: &str| relative_path(Path::new(path), Path::new(base));
+        assert_eq!(relative("/ws/src/lib.rs", "/ws"), Some("src/lib.rs".into()));
+        assert_eq!(
+            relative("/ws/src/lib.rs", "/ws/tests"),
+            Some("../src/lib.rs".into())
+        );
+        assert_eq!(
+            relative("/dep/lib.rs", "/ws/a"),
+            Some("../../dep/lib.rs".into())
+        );
+        assert_eq!(relative("/ws", "/ws"), Some(".".into()));
+        assert_eq!(relative("src/lib.rs", "/ws"), None);
+    }
+}
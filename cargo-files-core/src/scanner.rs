@@ -0,0 +1,350 @@
+//! A scanner which finds the declarations in a file from its tokens, without parsing it.
+//!
+//! Only module declarations, their attributes, and `include!`-like macro invocations matter to
+//! us, so there is no need to build a syntax tree for every function body. The scanner agrees
+//! with [`ModVisitor`]'s traversal of a syn syntax tree: in particular it doesn't look inside
+//! the bodies of macro invocations, which syn leaves as unparsed tokens.
+
+use crate::parser::{
+    Include, IncludeKind, ModVisitor, Module, PathComponent, parse_include_tokens,
+};
+use crate::span::Span;
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use std::str::FromStr;
+use syn::Meta;
+
+/// Keywords which can be followed by `!` without being a macro invocation, as in `if !done {}`.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "do", "dyn", "else",
+    "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+    "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+    "true", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+/// Return the declarations in the given source, or `None` if it can't be tokenized.
+///
+/// Unlike parsing with syn, other syntax errors go unnoticed.
+pub(crate) fn scan(source: &str) -> Option<ModVisitor> {
+    let tokens = TokenStream::from_str(strip_prefix(source)).ok()?;
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+    let mut visitor = ModVisitor::default();
+    scan_tokens(&mut visitor, &tokens);
+    Some(visitor)
+}
+
+/// Strip the byte order mark and shebang line from the start of the source, as syn does.
+///
+/// The newline ending the shebang is kept, so that line numbers are unaffected.
+fn strip_prefix(source: &str) -> &str {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    match source.strip_prefix("#!") {
+        // `#![...]` is an inner attribute rather than a shebang.
+        Some(rest) if !rest.trim_start().starts_with('[') => {
+            source.find('\n').map_or("", |newline| &source[newline..])
+        }
+        _ => source,
+    }
+}
+
+fn scan_tokens(visitor: &mut ModVisitor, tokens: &[TokenTree]) {
+    // The outer attributes of the item being scanned, which aren't known to belong to a module
+    // declaration until `mod` is seen.
+    let mut attributes = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        if let Some(attribute) = outer_attribute(&tokens[i..]) {
+            attributes.push(attribute);
+            i += 2;
+            continue;
+        }
+        if let Some(attribute) = inner_attribute(&tokens[i..]) {
+            scan_attribute(visitor, attribute);
+            i += 3;
+            continue;
+        }
+
+        match &tokens[i] {
+            // Visibility and `unsafe` may come between the attributes of a module and `mod`.
+            TokenTree::Ident(ident) if ident == "pub" => {
+                i += 1;
+                if matches!(tokens.get(i), Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis)
+                {
+                    i += 1;
+                }
+                continue;
+            }
+            TokenTree::Ident(ident) if ident == "unsafe" => {
+                i += 1;
+                continue;
+            }
+            TokenTree::Ident(ident) if ident == "mod" => {
+                if let Some(next) = scan_module(visitor, tokens, i, &attributes) {
+                    attributes.clear();
+                    i = next;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        // Anything else means the attributes belonged to some other item.
+        for attribute in attributes.drain(..) {
+            scan_attribute(visitor, attribute);
+        }
+
+        if let Some(body) = macro_body(tokens, i) {
+            if let Some(include) = include_at(visitor, tokens, i, body) {
+                visitor.includes.push(include);
+            }
+            // The contents of macro invocations are skipped, as they needn't be valid Rust.
+            i = body + 1;
+            continue;
+        }
+
+        if let TokenTree::Group(group) = &tokens[i] {
+            let inner = group.stream().into_iter().collect::<Vec<_>>();
+            scan_tokens(visitor, &inner);
+        }
+        i += 1;
+    }
+
+    for attribute in attributes {
+        scan_attribute(visitor, attribute);
+    }
+}
+
+/// Scan the module declaration starting with the `mod` token at `i`, returning the index of the
+/// token following it, or `None` if this isn't a module declaration.
+fn scan_module(
+    visitor: &mut ModVisitor,
+    tokens: &[TokenTree],
+    i: usize,
+    attributes: &[&Group],
+) -> Option<usize> {
+    let TokenTree::Ident(name) = tokens.get(i + 1)? else {
+        return None;
+    };
+    let (end, body) = match tokens.get(i + 2)? {
+        TokenTree::Punct(semi) if semi.as_char() == ';' => (semi.span(), None),
+        TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => (
+            name.span(),
+            Some(group.stream().into_iter().collect::<Vec<_>>()),
+        ),
+        _ => return None,
+    };
+
+    // Inner attributes at the start of an inline module apply to the module itself.
+    let mut attributes = attributes.to_vec();
+    let mut start = 0;
+    if let Some(body) = &body {
+        while let Some(attribute) = inner_attribute(&body[start..]) {
+            attributes.push(attribute);
+            start += 3;
+        }
+    }
+
+    let mut component = PathComponent::new(name.to_string());
+    for attribute in &attributes {
+        if let Ok(meta) = syn::parse2::<Meta>(attribute.stream()) {
+            component.parse_attribute(&meta, None);
+        }
+    }
+    visitor.stack.push(component);
+    visitor.modules.push(Module {
+        parts: visitor.stack.clone(),
+        span: Span::between(tokens[i].span(), end),
+        inline: body.is_some(),
+        outer: 0,
+    });
+
+    for attribute in attributes {
+        scan_attribute(visitor, attribute);
+    }
+    if let Some(body) = &body {
+        scan_tokens(visitor, &body[start..]);
+    }
+    visitor.stack.pop().expect("should be balanced");
+
+    Some(i + 3)
+}
+
+/// Record any `include!`-like macro invoked directly within an attribute, as in
+/// `#[doc = include_str!("README.md")]`.
+fn scan_attribute(visitor: &mut ModVisitor, attribute: &Group) {
+    let tokens = attribute.stream().into_iter().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < tokens.len() {
+        match macro_body(&tokens, i) {
+            Some(body) => {
+                if let Some(include) = include_at(visitor, &tokens, i, body) {
+                    visitor.includes.push(include);
+                }
+                i = body + 1;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+/// Return the contents of the outer attribute `#[...]` at the start of the tokens.
+fn outer_attribute(tokens: &[TokenTree]) -> Option<&Group> {
+    match tokens {
+        [TokenTree::Punct(pound), TokenTree::Group(group), ..]
+            if pound.as_char() == '#' && group.delimiter() == Delimiter::Bracket =>
+        {
+            Some(group)
+        }
+        _ => None,
+    }
+}
+
+/// Return the contents of the inner attribute `#![...]` at the start of the tokens.
+fn inner_attribute(tokens: &[TokenTree]) -> Option<&Group> {
+    match tokens {
+        [
+            TokenTree::Punct(pound),
+            TokenTree::Punct(bang),
+            TokenTree::Group(group),
+            ..,
+        ] if pound.as_char() == '#'
+            && bang.as_char() == '!'
+            && group.delimiter() == Delimiter::Bracket =>
+        {
+            Some(group)
+        }
+        _ => None,
+    }
+}
+
+/// If the identifier at `i` is the name of an invoked macro, as in `foo!(...)` or
+/// `macro_rules! foo {...}`, return the index of the macro's body.
+fn macro_body(tokens: &[TokenTree], i: usize) -> Option<usize> {
+    let TokenTree::Ident(ident) = &tokens[i] else {
+        return None;
+    };
+    if KEYWORDS.contains(&ident.to_string().as_str()) {
+        return None;
+    }
+    let Some(TokenTree::Punct(bang)) = tokens.get(i + 1) else {
+        return None;
+    };
+    if bang.as_char() != '!' {
+        return None;
+    }
+
+    match (tokens.get(i + 2), tokens.get(i + 3)) {
+        (Some(TokenTree::Group(_)), _) => Some(i + 2),
+        (Some(TokenTree::Ident(_)), Some(TokenTree::Group(_))) => Some(i + 3),
+        _ => None,
+    }
+}
+
+/// Return the include described by the macro invocation whose name is at `i` and whose body is
+/// at `body`, if it is an `include!`-like macro with a known argument.
+fn include_at(
+    visitor: &ModVisitor,
+    tokens: &[TokenTree],
+    i: usize,
+    body: usize,
+) -> Option<Include> {
+    let TokenTree::Group(group) = &tokens[body] else {
+        return None;
+    };
+    if body != i + 2 {
+        return None;
+    }
+
+    // Collect the path of the macro, such as `std::include`, by looking back for `::` separators.
+    let mut start = i;
+    let mut segments = vec![tokens[i].to_string()];
+    while start >= 3
+        && matches!(&tokens[start - 1], TokenTree::Punct(colon) if colon.as_char() == ':')
+        && matches!(&tokens[start - 2], TokenTree::Punct(colon) if colon.as_char() == ':')
+        && matches!(&tokens[start - 3], TokenTree::Ident(_))
+    {
+        segments.insert(0, tokens[start - 3].to_string());
+        start -= 3;
+    }
+
+    let kind = IncludeKind::from_path(&segments)?;
+    // Invocations whose argument isn't known until compile time, such as
+    // include!(concat!(env!("OUT_DIR"), "/foo.rs")), are skipped.
+    let path = parse_include_tokens(group.stream())?;
+    Some(Include {
+        kind,
+        path,
+        parts: visitor.stack.clone(),
+        span: Span::between(tokens[start].span(), group.span()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::visit::Visit;
+
+    /// Return the declarations found by the scanner and by syn, in a comparable form.
+    fn scan_and_parse(source: &str) -> (String, String) {
+        let scanned = scan(source).expect("source should tokenize");
+        let mut parsed = ModVisitor::default();
+        parsed.visit_file(&syn::parse_file(source).expect("source should parse"));
+        (format!("{scanned:?}"), format!("{parsed:?}"))
+    }
+
+    #[test]
+    fn test_scan_matches_syn() {
+        let source = r##"#!/usr/bin/env run-cargo-script
+            //! Crate docs.
+            #![doc = include_str!("../README.md")]
+
+            #[cfg(test)]
+            mod tests;
+
+            /// Documented.
+            #[cfg_attr(unix, path = "unix.rs")]
+            pub(crate) mod platform;
+
+            pub mod inline {
+                #![cfg(feature = "inline")]
+
+                #[path = "other.rs"]
+                mod nested;
+
+                fn f() -> bool {
+                    if !(1 < 2) {
+                        include!("body.rs");
+                    }
+                    let _ = std::include_bytes!("data.bin");
+                    !matches!(Some(1), None)
+                }
+            }
+
+            macro_rules! m {
+                () => { mod hidden; include!("hidden.rs"); };
+            }
+            m! { mod also_hidden; }
+
+            const X: &str = core::include_str!(concat!("a", "b.txt"));
+            const Y: &str = not::std::include_str!("ignored.txt");
+            include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+        "##;
+
+        let (scanned, parsed) = scan_and_parse(source);
+        assert_eq!(scanned, parsed);
+    }
+
+    #[test]
+    fn test_scan_unterminated() {
+        assert!(scan("mod a { mod b;").is_none());
+        assert!(scan("const S: &str = \"unterminated;").is_none());
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(strip_prefix("#!/bin/sh\nmod a;"), "\nmod a;");
+        assert_eq!(strip_prefix("\u{feff}mod a;"), "mod a;");
+        assert_eq!(strip_prefix("#![allow(dead_code)]"), "#![allow(dead_code)]");
+        assert_eq!(strip_prefix("#!/bin/sh"), "");
+    }
+}
@@ -0,0 +1,117 @@
+use crate::parser::{ParsedFiles, extract_crate_files_with};
+use crate::{Error, ModuleTree, Options, Target, TargetInputs, parallel};
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Collects the files of targets, remembering the modules declared in each file it parses.
+///
+/// Files shared between targets, such as helpers reached from several tests using `#[path]`,
+/// are only parsed once for the lifetime of the session. Files are assumed not to change in
+/// that time, unless they are passed to [`Session::forget`].
+#[derive(Debug, Default)]
+pub struct Session {
+    options: Options,
+    files: ParsedFiles,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        let files = match &options.cache_dir {
+            Some(dir) => ParsedFiles::with_disk_cache(dir, options.parse_mode),
+            None => ParsedFiles::new(options.parse_mode),
+        };
+        Session { options, files }
+    }
+
+    /// Return the options used to collect files.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Return the number of files whose declarations have been remembered.
+    pub fn parsed_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Forget the declarations of the given file, or of every file within the given directory,
+    /// so that they are parsed again when next needed.
+    ///
+    /// This should be called whenever a file changes during the lifetime of the session.
+    pub fn forget(&self, path: &Path) {
+        self.files.forget(path);
+    }
+
+    /// Get all source files for the given target.
+    pub fn target_files(&self, target: &Target) -> Result<HashSet<PathBuf>, Error> {
+        Ok(self.target_inputs(target)?.sources)
+    }
+
+    /// Get all source files and assets for the given target.
+    pub fn target_inputs(&self, target: &Target) -> Result<TargetInputs, Error> {
+        let mut inputs = self.all_target_inputs([target])?;
+        Ok(inputs.pop().expect("one target was given"))
+    }
+
+    /// Get all source files and assets for each of the given targets, in the same order.
+    ///
+    /// Files are parsed using [`Options::jobs`] threads. The results are the same as calling
+    /// [`Session::target_inputs`] for each target, each using the cfg set given by
+    /// [`Options::target_cfg`].
+    pub fn all_target_inputs<'a>(
+        &self,
+        targets: impl IntoIterator<Item = &'a Target>,
+    ) -> Result<Vec<TargetInputs>, Error> {
+        let targets = targets.into_iter().collect::<Vec<_>>();
+        if let Some(features) = &self.options.features {
+            features.check(targets.iter().copied())?;
+        }
+        let options = targets
+            .iter()
+            .map(|target| Options {
+                cfg: self.options.target_cfg(target),
+                ..self.options.clone()
+            })
+            .collect::<Vec<_>>();
+
+        let threads = self
+            .options
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get);
+        if threads > 1 {
+            let roots = targets
+                .iter()
+                .zip(&options)
+                .map(|(target, options)| (target.path.as_path(), options.cfg.as_ref()))
+                .collect::<Vec<_>>();
+            parallel::parse_all(&self.files, &roots, threads);
+        }
+
+        let inputs = targets
+            .into_iter()
+            .zip(&options)
+            .map(|(target, options)| {
+                let mut acc = TargetInputs {
+                    modules: ModuleTree::new(target.path.clone()),
+                    sources: HashSet::new(),
+                    assets: HashSet::new(),
+                    recovered: HashSet::new(),
+                    diagnostics: Vec::new(),
+                };
+                extract_crate_files_with(
+                    &target.path,
+                    &target.path,
+                    options,
+                    &mut acc,
+                    &self.files,
+                )?;
+                Ok(acc)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // The cache only saves time, so failing to write it isn't worth failing over.
+        let _ = self.files.save();
+        Ok(inputs)
+    }
+}
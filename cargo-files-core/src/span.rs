@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A position in a source file.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct LineColumn {
+    /// The 1-indexed line.
+    pub line: usize,
+    /// The 0-indexed column, in UTF-8 characters.
+    pub column: usize,
+}
+
+impl fmt::Display for LineColumn {
+    /// Format as `line:column`, with a 1-indexed column as in rustc diagnostics.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column + 1)
+    }
+}
+
+/// A region of a source file, such as a module declaration.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct Span {
+    pub start: LineColumn,
+    pub end: LineColumn,
+}
+
+impl Span {
+    /// Create a span covering the region from the start of `start` to the end of `end`.
+    pub(crate) fn between(start: proc_macro2::Span, end: proc_macro2::Span) -> Self {
+        let convert = |lc: proc_macro2::LineColumn| LineColumn {
+            line: lc.line,
+            column: lc.column,
+        };
+        Span {
+            start: convert(start.start()),
+            end: convert(end.end()),
+        }
+    }
+}
+
+impl From<proc_macro2::Span> for Span {
+    fn from(span: proc_macro2::Span) -> Self {
+        Span::between(span, span)
+    }
+}
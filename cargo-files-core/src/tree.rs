@@ -0,0 +1,199 @@
+use crate::cfg::CfgExpr;
+use crate::span::Span;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+
+/// Identifies a module within a [`ModuleTree`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModuleId(usize);
+
+/// Where the contents of a module come from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ModuleSource {
+    /// The module has its own source file.
+    File(PathBuf),
+    /// The module is written inline, as in `mod foo { ... }`.
+    Inline,
+}
+
+/// The location of a `mod` item.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Declaration {
+    /// The file containing the declaration.
+    pub file: PathBuf,
+    /// The location of the declaration within `file`.
+    pub span: Span,
+}
+
+/// A module within a [`ModuleTree`].
+#[derive(Debug, Clone)]
+pub struct ModuleNode {
+    /// The name of the module, or `crate` for the crate root.
+    pub name: String,
+    /// The fully qualified path of the module, such as `crate::net::tcp`.
+    pub path: String,
+    /// The source file of the module, or [`ModuleSource::Inline`].
+    pub source: ModuleSource,
+    /// The enclosing module, or `None` for the crate root.
+    pub parent: Option<ModuleId>,
+    /// The modules declared within this one, in the order they were found.
+    pub children: Vec<ModuleId>,
+    /// Whether the file of this module was chosen using a `#[path]` attribute, either on the
+    /// declaration itself or on an enclosing inline module.
+    pub has_path_attribute: bool,
+    /// The `#[cfg(...)]` predicates on the declaration of this module, all of which must hold
+    /// for it to be compiled. For an inline module declared several times, these are the
+    /// predicates of the first declaration.
+    pub cfgs: Vec<CfgExpr>,
+    /// The `mod` item declaring this module, or `None` for the crate root.
+    pub declaration: Option<Declaration>,
+    /// Files pulled in using `include!`, `include_str!` or `include_bytes!` within this module.
+    pub includes: Vec<PathBuf>,
+}
+
+impl ModuleNode {
+    /// Return the source file of this module, or `None` for inline modules.
+    pub fn file(&self) -> Option<&Path> {
+        match &self.source {
+            ModuleSource::File(path) => Some(path),
+            ModuleSource::Inline => None,
+        }
+    }
+}
+
+/// The module hierarchy of a target, rooted at the crate root.
+///
+/// Without a cfg set, a module whose file is selected using `#[cfg_attr(..., path = "...")]`
+/// appears once for each candidate file.
+#[derive(Debug, Clone)]
+pub struct ModuleTree {
+    nodes: Vec<ModuleNode>,
+}
+
+impl ModuleTree {
+    pub(crate) fn new(root_file: PathBuf) -> Self {
+        ModuleTree {
+            nodes: vec![ModuleNode {
+                name: String::from("crate"),
+                path: String::from("crate"),
+                source: ModuleSource::File(root_file),
+                parent: None,
+                children: Vec::new(),
+                has_path_attribute: false,
+                cfgs: Vec::new(),
+                declaration: None,
+                includes: Vec::new(),
+            }],
+        }
+    }
+
+    /// Return the crate root.
+    pub fn root(&self) -> ModuleId {
+        ModuleId(0)
+    }
+
+    /// Return the number of modules in the tree, including the crate root.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false, as the tree contains at least the crate root.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Iterate over every module in the tree, parents before their children.
+    pub fn iter(&self) -> impl Iterator<Item = (ModuleId, &ModuleNode)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (ModuleId(i), node))
+    }
+
+    /// Return every module whose source file is the given file.
+    pub fn modules_for_file<'a>(
+        &'a self,
+        file: &'a Path,
+    ) -> impl Iterator<Item = (ModuleId, &'a ModuleNode)> + 'a {
+        self.iter()
+            .filter(move |(_, node)| node.file() == Some(file))
+    }
+
+    /// Return every module which includes the given file using an `include!`-like macro.
+    pub fn modules_including_file<'a>(
+        &'a self,
+        file: &'a Path,
+    ) -> impl Iterator<Item = (ModuleId, &'a ModuleNode)> + 'a {
+        self.iter()
+            .filter(move |(_, node)| node.includes.iter().any(|include| include == file))
+    }
+
+    /// Add a module to the tree.
+    pub(crate) fn add(
+        &mut self,
+        parent: ModuleId,
+        name: &str,
+        source: ModuleSource,
+        has_path_attribute: bool,
+        cfgs: Vec<CfgExpr>,
+        declaration: Declaration,
+    ) -> ModuleId {
+        let id = ModuleId(self.nodes.len());
+        let path = format!("{}::{name}", self[parent].path);
+        self.nodes.push(ModuleNode {
+            name: name.to_string(),
+            path,
+            source,
+            parent: Some(parent),
+            children: Vec::new(),
+            has_path_attribute,
+            cfgs,
+            declaration: Some(declaration),
+            includes: Vec::new(),
+        });
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    /// Record that `module` includes the given file.
+    pub(crate) fn include(&mut self, module: ModuleId, file: PathBuf) {
+        let includes = &mut self.nodes[module.0].includes;
+        if !includes.contains(&file) {
+            includes.push(file);
+        }
+    }
+
+    /// Return the inline module with the given name within `parent`, adding it if necessary.
+    pub(crate) fn inline(
+        &mut self,
+        parent: ModuleId,
+        name: &str,
+        has_path_attribute: bool,
+        cfgs: &[CfgExpr],
+        declaration: Declaration,
+    ) -> ModuleId {
+        let existing = self[parent]
+            .children
+            .iter()
+            .find(|&&child| self[child].name == name && self[child].source == ModuleSource::Inline);
+        match existing {
+            Some(&child) => child,
+            None => self.add(
+                parent,
+                name,
+                ModuleSource::Inline,
+                has_path_attribute,
+                cfgs.to_vec(),
+                declaration,
+            ),
+        }
+    }
+}
+
+impl Index<ModuleId> for ModuleTree {
+    type Output = ModuleNode;
+
+    fn index(&self, id: ModuleId) -> &ModuleNode {
+        &self.nodes[id.0]
+    }
+}
@@ -1,30 +1,88 @@
+use cargo_files_core::{CfgOption, CfgSet};
+
 fn run_test(krate: &tempfile::TempDir) -> String {
+    run_test_with(krate, cargo_files_core::get_target_files)
+}
+
+/// Return the canonical root of a crate with a single target, and that target.
+fn single_target(krate: &tempfile::TempDir) -> (std::path::PathBuf, cargo_files_core::Target) {
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let target = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml")))
+        .unwrap()
+        .pop_first()
+        .unwrap();
+    (crate_root, target)
+}
+
+fn run_test_with_cfg(krate: &tempfile::TempDir, cfg: &[&str]) -> String {
+    let cfg: CfgSet = cfg
+        .iter()
+        .map(|option| CfgOption::parse(option).expect("invalid cfg option"))
+        .collect();
+    run_test_with(krate, |target| {
+        cargo_files_core::get_target_files_with_cfg(target, &cfg)
+    })
+}
+
+fn run_test_with<F>(krate: &tempfile::TempDir, get_files: F) -> String
+where
+    F: Fn(
+        &cargo_files_core::Target,
+    ) -> Result<std::collections::HashSet<std::path::PathBuf>, cargo_files_core::Error>,
+{
     let crate_root = dunce::canonicalize(krate.path()).unwrap();
     let projects = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
 
     let mut paths = Vec::new();
     for target in projects {
-        let files =
-            cargo_files_core::get_target_files(&target).expect("failed to get target files");
+        let files = get_files(&target).expect("failed to get target files");
         for file in files {
-            let relative_path = pathdiff::diff_paths(&file, &crate_root).unwrap();
-            let components: Vec<_> = relative_path
-                .components()
-                .map(|c| c.as_os_str().to_string_lossy().into_owned())
-                .collect();
-            paths.push(components.join("/"));
+            paths.push(relative_path(&file, &crate_root));
+        }
+    }
+    paths.sort();
+    paths.join("\n")
+}
+
+/// Like `run_test`, but lists the assets of each target as well as the source files.
+fn run_inputs_test(krate: &tempfile::TempDir) -> String {
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let projects = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let mut paths = Vec::new();
+    for target in projects {
+        let inputs = cargo_files_core::get_target_inputs(&target, &Default::default())
+            .expect("failed to get target inputs");
+        for file in inputs.sources {
+            paths.push(format!("source: {}", relative_path(&file, &crate_root)));
+        }
+        for file in inputs.assets {
+            paths.push(format!("asset: {}", relative_path(&file, &crate_root)));
         }
     }
     paths.sort();
     paths.join("\n")
 }
 
+fn relative_path(file: &std::path::Path, crate_root: &std::path::Path) -> String {
+    let relative_path = pathdiff::diff_paths(file, crate_root).unwrap();
+    let components: Vec<_> = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    components.join("/")
+}
+
 /// Generate a test case which detects which files are present in a crate.
 macro_rules! krate {
     ($def:literal) => {
         let krate = ::cargo_files_test::make_crate!($def);
         insta::assert_snapshot!(run_test(&krate));
     };
+    ($def:literal, cfg = [$($cfg:literal),*]) => {
+        let krate = ::cargo_files_test::make_crate!($def);
+        insta::assert_snapshot!(run_test_with_cfg(&krate, &[$($cfg),*]));
+    };
 }
 
 #[test]
@@ -199,3 +257,1288 @@ fn nested_module_paths_in_root() {
     "#
     );
 }
+
+#[test]
+fn cfg_without_cfg_set() {
+    // Without a cfg set every declared module is included.
+    krate!(
+        r##"
+        src:
+          - lib.rs: "#[cfg(unix)] mod unix; #[cfg(windows)] mod windows;"
+          - unix.rs
+          - windows.rs
+    "##
+    );
+}
+
+#[test]
+fn cfg_prunes_inactive_modules() {
+    // `windows.rs` doesn't exist, but isn't required because it is never compiled.
+    krate!(
+        r##"
+        src:
+          - lib.rs: "#[cfg(unix)] mod unix; #[cfg(windows)] mod windows;"
+          - unix.rs
+    "##,
+        cfg = ["unix"]
+    );
+}
+
+#[test]
+fn cfg_from_rustc() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg(target_arch = "arm")]
+              mod arm;
+              #[cfg_attr(target_os = "none", path = "bare_metal.rs")]
+              mod platform;
+              #[cfg(unix)]
+              mod unix;
+          - arm.rs
+          - bare_metal.rs
+          - platform.rs
+          - unix.rs
+    "##
+    );
+    let cfg = CfgSet::from_rustc(Some("thumbv7em-none-eabihf")).unwrap();
+    assert!(cfg.contains(&CfgOption::parse("target_arch=\"arm\"").unwrap()));
+    insta::assert_snapshot!(run_test_with(&krate, |target| {
+        cargo_files_core::get_target_files_with_cfg(target, &cfg)
+    }));
+
+    assert!(matches!(
+        CfgSet::from_rustc(Some("not-a-target")),
+        Err(cargo_files_core::Error::RustcError(_))
+    ));
+}
+
+#[test]
+fn cfg_from_rustc_test_targets() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "#[cfg(test)]\nmod unit;"
+          - unit.rs
+        tests:
+          - it:
+            - main.rs: "#[cfg(test)]\nmod helper;"
+            - helper.rs
+        benches:
+          - speed:
+            - main.rs: "#[cfg(test)]\nmod helper;"
+            - helper.rs
+    "##
+    );
+    // rustc's cfg set lacks `test`, which cargo sets for test and benchmark targets only.
+    let cfg = CfgSet::from_rustc(Some("thumbv7em-none-eabihf")).unwrap();
+    assert!(!cfg.contains(&CfgOption::Name(String::from("test"))));
+    insta::assert_snapshot!(run_test_with(&krate, |target| {
+        cargo_files_core::get_target_files_with_cfg(target, &cfg)
+    }));
+}
+
+#[test]
+fn cfg_nested_predicates() {
+    krate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg(feature = "net")]
+              mod net {
+                  #[cfg(all(unix, not(test)))]
+                  mod tcp;
+                  #[cfg(any(test, debug_assertions))]
+                  mod mock;
+              }
+              #[cfg(feature = "tls")]
+              mod tls;
+          - net:
+            - tcp.rs
+            - mock.rs
+    "##,
+        cfg = ["unix", "debug_assertions", "feature=\"net\""]
+    );
+}
+
+#[test]
+fn cfg_attr_path_without_cfg_set() {
+    // Every candidate path is reported, and the fallback `sys.rs` is optional.
+    krate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg_attr(unix, path = "unix.rs")]
+              #[cfg_attr(windows, path = "windows.rs")]
+              mod sys;
+          - unix.rs
+          - windows.rs
+    "##
+    );
+}
+
+#[test]
+fn cfg_attr_path_with_cfg_set() {
+    krate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg_attr(unix, path = "unix.rs")]
+              #[cfg_attr(windows, path = "windows.rs")]
+              mod sys;
+          - unix.rs
+          - windows.rs
+    "##,
+        cfg = ["windows"]
+    );
+}
+
+#[test]
+fn cfg_attr_path_fallback_with_cfg_set() {
+    // No cfg_attr applies, so the module is resolved using its name.
+    krate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg_attr(unix, path = "unix.rs")]
+              mod sys;
+          - sys.rs
+    "##,
+        cfg = ["windows"]
+    );
+}
+
+#[test]
+fn include_macros() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        docs:
+          - intro.md: "Hello!"
+        src:
+          - lib.rs: |
+              #![doc = include_str!("../docs/intro.md")]
+              include!("generated.rs");
+              mod inline {
+                  static DATA: &[u8] = include_bytes!(concat!("data", ".bin"));
+              }
+          - generated.rs: |
+              mod from_generated;
+              const TABLE: [u8; 1] = include!("table/values.in");
+          - from_generated.rs
+          - data.bin: ""
+          - table:
+            - values.in: "[0]"
+    "##
+    );
+    insta::assert_snapshot!(run_inputs_test(&krate));
+}
+
+#[test]
+fn include_from_subdirectory() {
+    // Included files resolve modules from their own directory, even within an inline module.
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              include!("sub/inc.rs");
+              mod wrapper {
+                  include!("other/inc.rs");
+              }
+          - sub:
+            - inc.rs: "pub mod inner;"
+            - inner.rs
+          - other:
+            - inc.rs: "mod nested;"
+            - nested.rs
+    "##
+    );
+    insta::assert_snapshot!(run_test(&krate));
+}
+
+fn run_error_test(krate: &tempfile::TempDir) -> String {
+    run_error_test_with(krate, &Default::default())
+}
+
+fn run_error_test_with(krate: &tempfile::TempDir, options: &cargo_files_core::Options) -> String {
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let projects = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let mut errors = Vec::new();
+    for target in projects {
+        let error =
+            cargo_files_core::get_target_inputs(&target, options).expect_err("expected an error");
+        // Make the absolute paths in the error message relative to the crate root.
+        let prefix = format!("{}{}", crate_root.display(), std::path::MAIN_SEPARATOR);
+        errors.push(error.to_string().replace(&prefix, ""));
+    }
+    errors.join("\n")
+}
+
+#[test]
+fn module_cycle_to_self() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[path = "lib.rs"]
+              mod again;
+    "##
+    );
+    insta::assert_snapshot!(run_error_test(&krate));
+}
+
+#[test]
+fn inline_module_path_ending_in_rs() {
+    // The path names a folder, so `b` is looked for in `src/foo.rs/`.
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[path = "foo.rs"]
+              mod a {
+                  mod b;
+              }
+          - foo.rs
+    "##
+    );
+    insta::assert_snapshot!(run_error_test(&krate));
+}
+
+#[test]
+fn module_cycle_between_files() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a]
+          - a.rs: |
+              #[path = "b.rs"]
+              mod x;
+          - b.rs: |
+              #[path = "a.rs"]
+              mod y;
+    "##
+    );
+    insta::assert_snapshot!(run_error_test(&krate));
+}
+
+#[test]
+fn include_cycle() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              include!("a.rs");
+          - a.rs: |
+              include!("a.rs");
+    "##
+    );
+    insta::assert_snapshot!(run_error_test(&krate));
+}
+
+#[test]
+fn include_unparseable() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              include!("generated.rs");
+          - generated.rs: |
+              mod lost;
+              fn unclosed() {
+          - lost.rs
+    "##
+    );
+    insta::assert_snapshot!(run_error_test(&krate));
+}
+
+#[test]
+fn module_not_found() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              // The declaration below is missing.
+              pub mod inline {
+                  mod missing;
+              }
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+
+    let error = cargo_files_core::get_target_files(&target).unwrap_err();
+    let cargo_files_core::Error::ModuleNotFound {
+        file,
+        span,
+        name,
+        candidates,
+    } = error
+    else {
+        panic!("expected ModuleNotFound, got {error:?}");
+    };
+    assert_eq!(relative_path(&file, &crate_root), "src/lib.rs");
+    assert_eq!(
+        (span.start.to_string(), span.end.to_string()),
+        ("3:5".into(), "3:17".into())
+    );
+    assert_eq!(name, "inline::missing");
+    assert_eq!(
+        candidates
+            .iter()
+            .map(|candidate| relative_path(candidate, &crate_root))
+            .collect::<Vec<_>>(),
+        vec!["src/inline/missing.rs", "src/inline/missing/mod.rs"]
+    );
+}
+
+#[test]
+fn parse_error() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [broken]
+          - broken.rs: "fn broken() {}\nstruct;"
+    "##
+    );
+    // Otherwise the file's modules are found by scanning it, with a warning.
+    let options = cargo_files_core::Options {
+        strict: true,
+        ..Default::default()
+    };
+    insta::assert_snapshot!(run_error_test_with(&krate, &options));
+}
+
+#[test]
+fn parse_mode() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [broken]
+          - broken.rs: "fn broken() {}\nstruct;"
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+
+    // Scanning only notices syntax errors which stop the file from being tokenized.
+    let options = cargo_files_core::Options {
+        parse_mode: cargo_files_core::ParseMode::Fast,
+        ..Default::default()
+    };
+    let inputs = cargo_files_core::get_target_inputs(&target, &options).unwrap();
+    assert_eq!(inputs.sources.len(), 2);
+    assert!(inputs.recovered.is_empty());
+    assert!(inputs.diagnostics.is_empty());
+
+    // A full parse warns about the syntax error, then carries on by scanning the file.
+    let options = cargo_files_core::Options::default();
+    let inputs = cargo_files_core::get_target_inputs(&target, &options).unwrap();
+    assert_eq!(inputs.sources.len(), 2);
+    let broken = crate_root.join("src/broken.rs");
+    assert_eq!(
+        inputs.recovered,
+        std::collections::HashSet::from([broken.clone()])
+    );
+    let [diagnostic] = &inputs.diagnostics[..] else {
+        panic!("expected one diagnostic: {:?}", inputs.diagnostics);
+    };
+    assert_eq!(diagnostic.severity, cargo_files_core::Severity::Warning);
+    assert_eq!(diagnostic.file(), Some(broken.as_path()));
+    assert_eq!(diagnostic.span().unwrap().start.line, 2);
+
+    // In strict mode, the syntax error is an error.
+    let options = cargo_files_core::Options {
+        strict: true,
+        ..options
+    };
+    assert!(matches!(
+        cargo_files_core::get_target_inputs(&target, &options),
+        Err(cargo_files_core::Error::ParseError { .. })
+    ));
+}
+
+#[test]
+fn parse_mode_unsupported_syntax() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "mod a;\nfn f() { yeet 1 + ; }"
+          - a.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+    let lib = crate_root.join("src/lib.rs");
+
+    // Files with syntax syn doesn't support are scanned like any other, without being marked.
+    let options = cargo_files_core::Options {
+        parse_mode: cargo_files_core::ParseMode::Fast,
+        ..Default::default()
+    };
+    let inputs = cargo_files_core::get_target_inputs(&target, &options).unwrap();
+    assert!(inputs.sources.contains(&crate_root.join("src/a.rs")));
+    assert!(inputs.recovered.is_empty());
+    assert!(inputs.diagnostics.is_empty());
+
+    // Only a full parse notices that syn can't parse them.
+    let options = cargo_files_core::Options::default();
+    let inputs = cargo_files_core::get_target_inputs(&target, &options).unwrap();
+    assert!(inputs.sources.contains(&crate_root.join("src/a.rs")));
+    assert_eq!(inputs.recovered, std::collections::HashSet::from([lib]));
+}
+
+#[test]
+fn keep_going() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a, missing, broken, b]
+          - a.rs
+          - b.rs [c]
+          - b:
+            - c.rs
+          - broken.rs: "mod never_visited;\nstruct;"
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+
+    // In strict mode, the modules of the unparseable file aren't recovered by scanning it.
+    let options = cargo_files_core::Options {
+        keep_going: true,
+        strict: true,
+        ..Default::default()
+    };
+    let inputs = cargo_files_core::get_target_inputs(&target, &options).unwrap();
+
+    let mut lines = inputs
+        .sources
+        .iter()
+        .map(|file| relative_path(file, &crate_root))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    // Make the absolute paths in the diagnostic messages relative to the crate root.
+    let prefix = format!("{}{}", crate_root.display(), std::path::MAIN_SEPARATOR);
+    for diagnostic in &inputs.diagnostics {
+        let file = relative_path(diagnostic.file().unwrap(), &crate_root);
+        let start = diagnostic.span().unwrap().start;
+        let message = diagnostic.message().replace(&prefix, "");
+        lines.push(format!(
+            "{}: {file}:{start}: {message}",
+            diagnostic.severity
+        ));
+    }
+    insta::assert_snapshot!(lines.join("\n"));
+}
+
+#[test]
+fn ambiguous_module() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a]
+          - a.rs
+          - a:
+            - mod.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+
+    // By default the ambiguity is a warning, and `a.rs` is preferred.
+    let inputs = cargo_files_core::get_target_inputs(&target, &Default::default()).unwrap();
+    let mut sources = inputs
+        .sources
+        .iter()
+        .map(|file| relative_path(file, &crate_root))
+        .collect::<Vec<_>>();
+    sources.sort();
+    assert_eq!(sources, vec!["src/a.rs", "src/lib.rs"]);
+    assert_eq!(inputs.diagnostics.len(), 1);
+    assert_eq!(
+        inputs.diagnostics[0].severity,
+        cargo_files_core::Severity::Warning
+    );
+
+    // In strict mode it is an error.
+    let options = cargo_files_core::Options {
+        strict: true,
+        ..Default::default()
+    };
+    let error = cargo_files_core::get_target_inputs(&target, &options).unwrap_err();
+    let prefix = format!("{}{}", crate_root.display(), std::path::MAIN_SEPARATOR);
+    assert_eq!(
+        error.to_string().replace(&prefix, ""),
+        "file for module `a` found at both src/a.rs and src/a/mod.rs"
+    );
+}
+
+#[test]
+fn missing_include_in_cfg_gated_module() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              mod a;
+              #[cfg(test)]
+              mod tests {
+                  const DATA: &str = include_str!("../testdata/input.txt");
+              }
+          - a.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+
+    // Without a cfg set, the missing file may never be needed, so it is only a warning.
+    let inputs = cargo_files_core::get_target_inputs(&target, &Default::default()).unwrap();
+    let mut sources = inputs
+        .sources
+        .iter()
+        .map(|file| relative_path(file, &crate_root))
+        .collect::<Vec<_>>();
+    sources.sort();
+    assert_eq!(sources, vec!["src/a.rs", "src/lib.rs"]);
+    assert_eq!(inputs.diagnostics.len(), 1);
+    assert_eq!(
+        inputs.diagnostics[0].severity,
+        cargo_files_core::Severity::Warning
+    );
+
+    // With a cfg set enabling the module, it is an error.
+    let options = cargo_files_core::Options {
+        cfg: Some(
+            [cargo_files_core::CfgOption::Name(String::from("test"))]
+                .into_iter()
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    assert!(cargo_files_core::get_target_inputs(&target, &options).is_err());
+}
+
+#[test]
+fn module_tree() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "mod a;\n#[path = \"other.rs\"]\nmod b;\nmod inline {\n    #[cfg(feature = \"c\")]\n    mod c;\n}"
+          - a.rs [d]
+          - a:
+            - d.rs
+          - other.rs
+          - inline:
+            - c.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+    let inputs = cargo_files_core::get_target_inputs(&target, &Default::default()).unwrap();
+
+    fn render(
+        tree: &cargo_files_core::ModuleTree,
+        id: cargo_files_core::ModuleId,
+        crate_root: &std::path::Path,
+        depth: usize,
+        lines: &mut Vec<String>,
+    ) {
+        let node = &tree[id];
+        let source = match node.file() {
+            Some(file) => relative_path(file, crate_root),
+            None => String::from("inline"),
+        };
+        let mut line = format!("{}{} ({source})", "  ".repeat(depth), node.path);
+        if node.has_path_attribute {
+            line.push_str(" #[path]");
+        }
+        for cfg in &node.cfgs {
+            line.push_str(&format!(" #[cfg({cfg})]"));
+        }
+        if let Some(declaration) = &node.declaration {
+            line.push_str(&format!(
+                " declared at {}:{}",
+                relative_path(&declaration.file, crate_root),
+                declaration.span.start
+            ));
+        }
+        lines.push(line);
+        for &child in &node.children {
+            render(tree, child, crate_root, depth + 1, lines);
+        }
+    }
+
+    let mut lines = Vec::new();
+    render(
+        &inputs.modules,
+        inputs.modules.root(),
+        &crate_root,
+        0,
+        &mut lines,
+    );
+    insta::assert_snapshot!(lines.join("\n"));
+}
+
+#[test]
+fn file_owners() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "mod shared;\nmod generated {\n    include!(\"generated.rs\");\n}"
+          - shared.rs
+          - generated.rs
+          - unused.rs
+          - bin:
+            - cli.rs: "#[path = \"../shared.rs\"]\nmod common;\nfn main() {}"
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let targets = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let files = [
+        "src/lib.rs",
+        "src/shared.rs",
+        "src/generated.rs",
+        "src/unused.rs",
+    ]
+    .map(|file| crate_root.join(file))
+    .to_vec();
+    let owners = cargo_files_core::get_file_owners(&targets, &files, &Default::default()).unwrap();
+
+    let mut lines = Vec::new();
+    for (file, owners) in files.iter().zip(owners) {
+        let mut owners = owners
+            .iter()
+            .map(|owner| {
+                format!(
+                    "{} {} {} ({:?})",
+                    owner.target.kind, owner.target.name, owner.module_path, owner.reached_by
+                )
+            })
+            .collect::<Vec<_>>();
+        owners.sort();
+        lines.push(format!(
+            "{}: [{}]",
+            relative_path(file, &crate_root),
+            owners.join(", ")
+        ));
+    }
+    insta::assert_snapshot!(lines.join("\n"));
+}
+
+#[test]
+fn orphan_files() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [used]
+          - used.rs
+          - stale.rs
+          - generated.rs
+          - .gitignore: "generated.rs"
+          - old:
+            - stale.rs
+        target:
+          - debug:
+            - build.rs
+        nested:
+          - Cargo.toml: "[package]\nname = \"nested\"\nversion = \"0.1.0\""
+          - src:
+            - lib.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let targets = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let orphans = cargo_files_core::get_orphan_files(&targets, &Default::default()).unwrap();
+    let orphans = orphans
+        .iter()
+        .map(|file| relative_path(file, &crate_root))
+        .collect::<Vec<_>>();
+    assert_eq!(orphans, vec!["src/old/stale.rs", "src/stale.rs"]);
+}
+
+#[test]
+fn parallel_traversal_matches_serial() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "mod a;\nmod b;\nmod generated {\n    include!(\"generated.rs\");\n}"
+          - generated.rs: "mod c;"
+          - a.rs [d, e]
+          - a:
+            - d.rs
+            - e.rs
+          - b.rs
+          - generated:
+            - c.rs
+          - bin:
+            - one.rs: "#[path = \"../a.rs\"]\nmod a;\nfn main() {}"
+            - two.rs: "#[path = \"../b.rs\"]\nmod b;\nmod missing;\nfn main() {}"
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let targets = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let collect = |jobs: usize| {
+        let options = cargo_files_core::Options {
+            keep_going: true,
+            jobs: std::num::NonZeroUsize::new(jobs),
+            ..Default::default()
+        };
+        let inputs = cargo_files_core::get_all_target_inputs(&targets, &options).unwrap();
+        inputs
+            .iter()
+            .map(|inputs| {
+                let mut sources = inputs.sources.iter().cloned().collect::<Vec<_>>();
+                sources.sort();
+                let modules = inputs
+                    .modules
+                    .iter()
+                    .map(|(_, node)| (node.path.clone(), node.source.clone()))
+                    .collect::<Vec<_>>();
+                let diagnostics = inputs
+                    .diagnostics
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                (sources, modules, diagnostics)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let serial = collect(1);
+    assert_eq!(serial.len(), 3);
+    for _ in 0..10 {
+        assert_eq!(collect(8), serial);
+    }
+}
+
+#[test]
+fn session_parses_shared_files_once() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [helpers]
+          - helpers.rs [inner]
+          - helpers:
+            - inner.rs
+          - bin:
+            - one.rs: "#[path = \"../helpers.rs\"]\nmod helpers;\nfn main() {}"
+            - two.rs: "#[path = \"../helpers.rs\"]\nmod helpers;\nfn main() {}"
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let targets = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+
+    let session = cargo_files_core::Session::default();
+    let first = session.all_target_inputs(&targets).unwrap();
+    // lib.rs, one.rs, two.rs, helpers.rs and helpers/inner.rs.
+    assert_eq!(session.parsed_files(), 5);
+
+    // The declarations in each file are remembered, so the helpers needn't be read again.
+    std::fs::write(crate_root.join("src/helpers.rs"), "this isn't rust").unwrap();
+    for (target, first) in targets.iter().zip(first) {
+        let second = session.target_inputs(target).unwrap();
+        assert_eq!(second.sources, first.sources);
+    }
+    assert_eq!(session.parsed_files(), 5);
+}
+
+#[test]
+fn disk_cache() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a]
+          - a.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+    let cache_dir = tempfile::tempdir().unwrap();
+    let options = cargo_files_core::Options {
+        cache_dir: Some(cache_dir.path().to_path_buf()),
+        strict: true,
+        ..Default::default()
+    };
+    let collect = || cargo_files_core::Session::new(options.clone()).target_files(&target);
+    let sources = collect().unwrap();
+    assert_eq!(sources.len(), 2);
+
+    // Replace lib.rs with something unparseable, keeping its size and modification time, so
+    // that the cached declarations are still used.
+    let lib_rs = crate_root.join("src/lib.rs");
+    let modified = std::fs::metadata(&lib_rs).unwrap().modified().unwrap();
+    let garbage = "?".repeat(std::fs::metadata(&lib_rs).unwrap().len() as usize);
+    std::fs::write(&lib_rs, &garbage).unwrap();
+    let file = std::fs::File::options().write(true).open(&lib_rs).unwrap();
+    file.set_modified(modified).unwrap();
+    assert_eq!(collect().unwrap(), sources);
+
+    // Once the modification time changes, the file is parsed again.
+    file.set_modified(modified + std::time::Duration::from_secs(1))
+        .unwrap();
+    assert!(matches!(
+        collect(),
+        Err(cargo_files_core::Error::ParseError { .. })
+    ));
+
+    // Without a cache directory, nothing is reused.
+    file.set_modified(modified).unwrap();
+    let options = cargo_files_core::Options {
+        cache_dir: None,
+        ..options
+    };
+    assert!(cargo_files_core::get_target_inputs(&target, &options).is_err());
+}
+
+#[test]
+fn disk_cache_prunes_deleted_files() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a]
+          - a.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+    let cache_dir = tempfile::tempdir().unwrap();
+    let options = cargo_files_core::Options {
+        cache_dir: Some(cache_dir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let cached_files = || {
+        cargo_files_core::Session::new(options.clone())
+            .target_files(&target)
+            .unwrap();
+        let contents = std::fs::read(cache_dir.path().join("declarations.json")).unwrap();
+        let cache: serde_json::Value = serde_json::from_slice(&contents).unwrap();
+        let mut files = cache["entries"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|file| relative_path(std::path::Path::new(file), &crate_root))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    assert_eq!(cached_files(), vec!["src/a.rs", "src/lib.rs"]);
+
+    std::fs::remove_file(crate_root.join("src/a.rs")).unwrap();
+    std::fs::write(crate_root.join("src/lib.rs"), "// No modules left.").unwrap();
+    assert_eq!(cached_files(), vec!["src/lib.rs"]);
+}
+
+#[test]
+fn workspace_root() {
+    let root = tempfile::tempdir().unwrap();
+    let root_dir = dunce::canonicalize(root.path()).unwrap();
+    let member = root_dir.join("member");
+    std::fs::create_dir_all(member.join("src")).unwrap();
+    std::fs::write(member.join("src/lib.rs"), "").unwrap();
+    std::fs::write(
+        member.join("Cargo.toml"),
+        "[package]\nname = \"member\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    let workspace_root = |manifest: &str| {
+        std::fs::write(root_dir.join("Cargo.toml"), manifest).unwrap();
+        cargo_files_core::get_workspace_root(Some(&member.join("Cargo.toml"))).unwrap()
+    };
+
+    assert_eq!(
+        workspace_root("[workspace] # The root.\nmembers = [\"member\"]\n"),
+        root_dir
+    );
+    assert_eq!(
+        workspace_root("workspace = { members = [\"member\"] }\n"),
+        root_dir
+    );
+    assert_eq!(workspace_root("# Not a workspace.\n"), member);
+}
+
+#[test]
+fn cached_targets() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let manifest_path = crate_root.join("Cargo.toml");
+    let cache_dir = tempfile::tempdir().unwrap();
+    let target_names = || {
+        cargo_files_core::get_targets_cached(Some(&manifest_path), cache_dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|target| format!("{} {}", target.kind, target.name))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(target_names(), vec!["lib test_case"]);
+    assert!(cache_dir.path().join("targets.json").exists());
+    assert_eq!(target_names(), vec!["lib test_case"]);
+
+    // Adding a file where cargo discovers targets invalidates the cache.
+    std::fs::create_dir(crate_root.join("src/bin")).unwrap();
+    std::fs::write(crate_root.join("src/bin/tool.rs"), "fn main() {}").unwrap();
+    let mut names = target_names();
+    names.sort();
+    assert_eq!(names, vec!["bin tool", "lib test_case"]);
+
+    // As does changing the manifest.
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace("[dependencies]", "autobins = false\n\n[dependencies]");
+    std::fs::write(&manifest_path, manifest).unwrap();
+    assert_eq!(target_names(), vec!["lib test_case"]);
+}
+
+#[test]
+fn target_identity() {
+    use cargo_files_core::{CrateType, TargetKind};
+
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs
+          - bin:
+            - foo.rs
+        examples:
+          - foo.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let manifest_path = crate_root.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace(
+        "[dependencies]",
+        r#"[lib]
+crate-type = ["lib", "cdylib"]
+doctest = false
+
+[[bin]]
+name = "same-root"
+path = "src/lib.rs"
+
+[[example]]
+name = "foo"
+required-features = ["extra"]
+
+[features]
+extra = []
+
+[dependencies]"#,
+    );
+    std::fs::write(&manifest_path, manifest).unwrap();
+
+    let targets = cargo_files_core::get_targets(Some(&manifest_path)).unwrap();
+    let mut names = targets
+        .iter()
+        .map(|target| format!("{} {}", target.kind, target.name))
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["bin foo", "bin same-root", "example foo", "lib test_case"]
+    );
+
+    let lib = targets
+        .iter()
+        .find(|target| target.kind == TargetKind::Lib)
+        .unwrap();
+    assert_eq!(lib.kinds, vec![TargetKind::Lib, TargetKind::CDyLib]);
+    assert_eq!(lib.crate_types, vec![CrateType::Lib, CrateType::CDyLib]);
+    assert!(!lib.doctest);
+    assert!(lib.test);
+    assert_eq!(lib.package, "test-case");
+    assert_eq!(lib.manifest_path, manifest_path);
+
+    let example = targets
+        .iter()
+        .find(|target| target.kind == TargetKind::Example)
+        .unwrap();
+    assert_eq!(example.required_features, vec!["extra"]);
+    assert_eq!(example.package_id, lib.package_id);
+}
+
+#[test]
+fn target_filter() {
+    use cargo_files_core::{Error, TargetFilter, TargetSelection};
+
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs
+          - main.rs
+          - bin:
+            - tool.rs
+        tests:
+          - it.rs
+        examples:
+          - demo.rs
+        helper:
+          - Cargo.toml: "[package]\nname = \"helper\"\nversion = \"0.1.0\""
+          - src:
+            - lib.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let manifest_path = crate_root.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace(
+        "[dependencies]",
+        "[dependencies]\nhelper = { path = \"helper\" }",
+    );
+    std::fs::write(&manifest_path, manifest).unwrap();
+
+    let targets = cargo_files_core::get_targets(Some(&manifest_path)).unwrap();
+    let target_names = |filter: TargetFilter| {
+        let mut names = filter
+            .apply(targets.clone())
+            .unwrap()
+            .into_iter()
+            .map(|target| format!("{} {} {}", target.package, target.kind, target.name))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    assert_eq!(target_names(TargetFilter::default()).len(), 6);
+    assert_eq!(
+        target_names(TargetFilter {
+            packages: vec![String::from("helper")],
+            ..Default::default()
+        }),
+        vec!["helper lib helper"]
+    );
+    assert_eq!(
+        target_names(TargetFilter {
+            workspace: true,
+            lib: true,
+            ..Default::default()
+        }),
+        vec!["test-case lib test_case"]
+    );
+    assert_eq!(
+        target_names(TargetFilter {
+            exclude: vec![String::from("test-case")],
+            ..Default::default()
+        }),
+        vec!["helper lib helper"]
+    );
+    assert_eq!(
+        target_names(TargetFilter {
+            bins: TargetSelection::Named(vec![String::from("tool")]),
+            tests: TargetSelection::All,
+            ..Default::default()
+        }),
+        vec!["test-case bin tool", "test-case test it"]
+    );
+    assert_eq!(
+        target_names(TargetFilter {
+            workspace: true,
+            ..TargetFilter::all_targets()
+        })
+        .len(),
+        5
+    );
+
+    let missing = TargetFilter {
+        examples: TargetSelection::Named(vec![String::from("missing")]),
+        ..Default::default()
+    };
+    assert!(matches!(
+        missing.apply(targets.clone()),
+        Err(Error::TargetNotFound { .. })
+    ));
+    let missing = TargetFilter {
+        packages: vec![String::from("missing")],
+        ..Default::default()
+    };
+    assert!(matches!(
+        missing.apply(targets),
+        Err(Error::PackageNotFound(_))
+    ));
+}
+
+#[test]
+fn feature_selection() {
+    use cargo_files_core::{CfgOption, FeatureSelection, Options};
+
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[cfg(feature = "tls")]
+              mod tls;
+              #[cfg(feature = "extra")]
+              mod extra;
+              #[cfg(feature = "helper")]
+              mod helper;
+              #[cfg(unix)]
+              mod unix;
+          - tls.rs
+          - extra.rs
+          - helper.rs
+          - unix.rs
+        helper:
+          - Cargo.toml: "[package]\nname = \"helper\"\nversion = \"0.1.0\"\n\n[features]\nfast = []"
+          - src:
+            - lib.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let manifest_path = crate_root.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace(
+        "[dependencies]",
+        r#"[features]
+default = ["tls"]
+tls = ["helper/fast"]
+extra = []
+
+[dependencies]
+helper = { path = "helper", optional = true }"#,
+    );
+    std::fs::write(&manifest_path, manifest).unwrap();
+
+    let target = cargo_files_core::get_targets(Some(&manifest_path))
+        .unwrap()
+        .into_iter()
+        .find(|target| target.package == "test-case")
+        .unwrap();
+    let file_names = |features: FeatureSelection| {
+        let options = Options {
+            cfg: Some(
+                [CfgOption::Name(String::from("unix"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            features: Some(features),
+            ..Default::default()
+        };
+        let mut names = cargo_files_core::get_target_inputs(&target, &options)
+            .unwrap()
+            .sources
+            .iter()
+            .map(|file| relative_path(file, &crate_root))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    // `tls` enables a feature of the optional dependency `helper`, and so the dependency's
+    // implicit feature.
+    assert_eq!(
+        file_names(FeatureSelection::default()),
+        vec!["src/helper.rs", "src/lib.rs", "src/tls.rs", "src/unix.rs"]
+    );
+    assert_eq!(
+        file_names(FeatureSelection {
+            features: vec![String::from("extra")],
+            no_default_features: true,
+            ..Default::default()
+        }),
+        vec!["src/extra.rs", "src/lib.rs", "src/unix.rs"]
+    );
+    assert_eq!(
+        file_names(FeatureSelection {
+            features: vec![String::from("test-case/extra")],
+            no_default_features: true,
+            ..Default::default()
+        }),
+        vec!["src/extra.rs", "src/lib.rs", "src/unix.rs"]
+    );
+    assert_eq!(
+        file_names(FeatureSelection {
+            features: vec![String::from("helper")],
+            no_default_features: true,
+            ..Default::default()
+        }),
+        vec!["src/helper.rs", "src/lib.rs", "src/unix.rs"]
+    );
+    assert_eq!(
+        file_names(FeatureSelection {
+            all_features: true,
+            ..Default::default()
+        })
+        .len(),
+        5
+    );
+
+    let options = Options {
+        cfg: Some(cargo_files_core::CfgSet::new()),
+        features: Some(FeatureSelection {
+            features: vec![String::from("missing")],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert!(matches!(
+        cargo_files_core::get_target_inputs(&target, &options),
+        Err(cargo_files_core::Error::FeatureNotFound(_))
+    ));
+}
+
+#[test]
+fn feature_selection_test_targets() {
+    use cargo_files_core::{FeatureSelection, Options, TargetKind};
+
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: "#[cfg(test)]\nmod unit;"
+          - unit.rs
+        tests:
+          - it:
+            - main.rs: "#[cfg(test)]\nmod helper;"
+            - helper.rs
+    "##
+    );
+    let crate_root = dunce::canonicalize(krate.path()).unwrap();
+    let targets = cargo_files_core::get_targets(Some(&crate_root.join("Cargo.toml"))).unwrap();
+    let options = Options {
+        cfg: Some(cargo_files_core::CfgSet::new()),
+        features: Some(FeatureSelection::default()),
+        ..Default::default()
+    };
+    let file_names = |kind: TargetKind| {
+        let target = targets.iter().find(|target| target.kind == kind).unwrap();
+        let mut names = cargo_files_core::get_target_inputs(target, &options)
+            .unwrap()
+            .sources
+            .iter()
+            .map(|file| relative_path(file, &crate_root))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    // Integration tests are built with `--test`, and so with `cfg(test)`, unlike libraries.
+    assert_eq!(
+        file_names(TargetKind::Test),
+        vec!["tests/it/helper.rs", "tests/it/main.rs"]
+    );
+    assert_eq!(file_names(TargetKind::Lib), vec!["src/lib.rs"]);
+}
+
+#[test]
+fn session_forget() {
+    let krate = ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs [a]
+          - a.rs
+          - b.rs
+    "##
+    );
+    let (crate_root, target) = single_target(&krate);
+    let session = cargo_files_core::Session::default();
+    assert_eq!(session.target_files(&target).unwrap().len(), 2);
+
+    // Changes aren't seen until the file is forgotten.
+    std::fs::write(crate_root.join("src/lib.rs"), "mod a;\nmod b;").unwrap();
+    assert_eq!(session.target_files(&target).unwrap().len(), 2);
+    session.forget(&crate_root.join("src/lib.rs"));
+    assert_eq!(session.target_files(&target).unwrap().len(), 3);
+
+    // Forgetting a directory forgets every file within it.
+    std::fs::write(crate_root.join("src/lib.rs"), "mod a;").unwrap();
+    session.forget(&crate_root.join("src"));
+    assert_eq!(session.target_files(&target).unwrap().len(), 2);
+}
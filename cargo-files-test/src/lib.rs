@@ -21,7 +21,7 @@ fn file_regex() -> &'static Regex {
         Regex::new(r"^(?P<name>\w+(\.rs)?)\s*?(\s+\[(?P<modules>(\w+)(\(.*?\))?(\s*,\s*?\w+)*)])?$")
             .expect("failed to compile regex")
     });
-    &*FILE_REGEX
+    &FILE_REGEX
 }
 
 #[derive(Clone, Debug)]
@@ -120,12 +120,15 @@ impl<'de> Deserialize<'de> for File {
 enum DirTreeEntry {
     File(File),
     Directory(DirTree),
+    /// A file with verbatim contents, such as `lib.rs: "#[cfg(unix)] mod a;"`.
+    Raw(HashMap<String, String>),
 }
 
 #[derive(Debug)]
 struct Dir {
     path: Vec<String>,
     files: Vec<File>,
+    raw_files: Vec<(String, String)>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -137,6 +140,7 @@ impl DirTree {
             let mut current_dir = Dir {
                 path: vec![folder.clone()],
                 files: Vec::new(),
+                raw_files: Vec::new(),
             };
 
             let mut buf = Vec::new();
@@ -148,6 +152,11 @@ impl DirTree {
                     DirTreeEntry::Directory(dir) => {
                         dir.dirs(&mut buf);
                     }
+                    DirTreeEntry::Raw(raw) => {
+                        current_dir
+                            .raw_files
+                            .extend(raw.iter().map(|(k, v)| (k.clone(), v.clone())));
+                    }
                 }
             }
 
@@ -209,12 +218,21 @@ pub fn make_crate(item: TokenStream) -> TokenStream {
                 }
             });
 
+            let raw_file_creation = dir.raw_files.iter().map(|(path, contents)| {
+                quote! {
+                    path.push(#path);
+                    ::std::fs::write(&path, #contents).expect("failed to write file");
+                    path.pop();
+                }
+            });
+
             quote! {
                 {
                     let suffix: ::std::path::PathBuf = [#(#path),*].iter().collect();
                     let mut path = dir.path().join(suffix);
                     ::std::fs::create_dir(&path).expect("failed to create directory");
                     #(#file_creation)*
+                    #(#raw_file_creation)*
                 }
             }
         })
@@ -0,0 +1,250 @@
+//! Rendering the module hierarchy of targets as a graph, in Graphviz's DOT language or as a
+//! Mermaid flowchart.
+//!
+//! Each module is a node, and each `mod` declaration an edge from the declaring module. Nodes
+//! are clustered by target, and targets by package. Edges to modules whose file was chosen using
+//! a `#[path]` attribute are dashed, and edges to modules with `#[cfg(...)]` predicates are
+//! coloured and labelled with the predicates.
+
+use crate::output::Output;
+use cargo_files_core::{ModuleNode, ModuleTree, Target};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The colour of edges to modules with `#[cfg(...)]` predicates.
+const CFG_COLOUR: &str = "blue";
+
+/// The language a graph is rendered in.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz's DOT language, as rendered by `dot -Tsvg`
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, as rendered by GitHub in ```mermaid blocks
+    Mermaid,
+}
+
+/// A `mod` declaration, from the module declaring it to the declared module.
+struct Edge {
+    from: String,
+    to: String,
+    path: bool,
+    cfg: Option<String>,
+}
+
+impl Edge {
+    fn label(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.path {
+            parts.push(String::from("#[path]"));
+        }
+        parts.extend(self.cfg.clone());
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// Render the module hierarchies of the given targets as a single graph.
+///
+/// The targets are expected to be sorted, so that the targets of each package are adjacent.
+pub fn render(targets: &[(&Target, &ModuleTree)], output: &Output, format: GraphFormat) -> String {
+    let mut packages: Vec<Vec<(usize, &Target, &ModuleTree)>> = Vec::new();
+    for (i, &(target, modules)) in targets.iter().enumerate() {
+        match packages.last_mut() {
+            Some(package) if package[0].1.package_id == target.package_id => {
+                package.push((i, target, modules));
+            }
+            _ => packages.push(vec![(i, target, modules)]),
+        }
+    }
+
+    match format {
+        GraphFormat::Dot => render_dot(&packages, output),
+        GraphFormat::Mermaid => render_mermaid(&packages, output),
+    }
+}
+
+fn render_dot(packages: &[Vec<(usize, &Target, &ModuleTree)>], output: &Output) -> String {
+    let mut out = String::from("digraph modules {\n    rankdir=LR;\n    node [shape=box];\n");
+    for (p, package) in packages.iter().enumerate() {
+        let name = &package[0].1.package;
+        writeln!(out, "    subgraph cluster_{p} {{").unwrap();
+        writeln!(out, "        label=\"{}\";", escape_dot(name)).unwrap();
+        for &(t, target, modules) in package {
+            writeln!(out, "        subgraph cluster_{p}_{t} {{").unwrap();
+            writeln!(
+                out,
+                "            label=\"{}\";",
+                escape_dot(&describe_target(target))
+            )
+            .unwrap();
+            for (i, (_, node)) in modules.iter().enumerate() {
+                let label = node_label(node, output)
+                    .iter()
+                    .map(|line| escape_dot(line))
+                    .collect::<Vec<_>>()
+                    .join("\\n");
+                let shape = if node.file().is_none() {
+                    ", shape=ellipse"
+                } else {
+                    ""
+                };
+                writeln!(out, "            m{t}_{i} [label=\"{label}\"{shape}];").unwrap();
+            }
+            for edge in edges(t, modules) {
+                let mut attributes = Vec::new();
+                if edge.path {
+                    attributes.push(String::from("style=dashed"));
+                }
+                if edge.cfg.is_some() {
+                    attributes.push(format!("color={CFG_COLOUR}, fontcolor={CFG_COLOUR}"));
+                }
+                if let Some(label) = edge.label() {
+                    attributes.push(format!("label=\"{}\"", escape_dot(&label)));
+                }
+                let attributes = if attributes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", attributes.join(", "))
+                };
+                writeln!(out, "            {} -> {}{attributes};", edge.from, edge.to).unwrap();
+            }
+            writeln!(out, "        }}").unwrap();
+        }
+        writeln!(out, "    }}").unwrap();
+    }
+    out.push('}');
+    out
+}
+
+fn render_mermaid(packages: &[Vec<(usize, &Target, &ModuleTree)>], output: &Output) -> String {
+    let mut out = String::from("flowchart LR\n");
+    // Links can only be styled by their index, in the order they are declared.
+    let mut links = 0;
+    let mut cfg_links = Vec::new();
+    for (p, package) in packages.iter().enumerate() {
+        let name = &package[0].1.package;
+        writeln!(out, "    subgraph p{p} [\"{}\"]", escape_mermaid(name)).unwrap();
+        for &(t, target, modules) in package {
+            writeln!(
+                out,
+                "        subgraph p{p}_t{t} [\"{}\"]",
+                escape_mermaid(&describe_target(target))
+            )
+            .unwrap();
+            for (i, (_, node)) in modules.iter().enumerate() {
+                let label = node_label(node, output)
+                    .iter()
+                    .map(|line| escape_mermaid(line))
+                    .collect::<Vec<_>>()
+                    .join("<br>");
+                // Inline modules are drawn with rounded corners.
+                let (open, close) = if node.file().is_none() {
+                    ("(", ")")
+                } else {
+                    ("[", "]")
+                };
+                writeln!(out, "            m{t}_{i}{open}\"{label}\"{close}").unwrap();
+            }
+            for edge in edges(t, modules) {
+                let arrow = match (edge.path, edge.label()) {
+                    (false, None) => String::from("-->"),
+                    (true, None) => String::from("-.->"),
+                    (false, Some(label)) => format!("-- \"{}\" -->", escape_mermaid(&label)),
+                    (true, Some(label)) => format!("-. \"{}\" .->", escape_mermaid(&label)),
+                };
+                writeln!(out, "            {} {arrow} {}", edge.from, edge.to).unwrap();
+                if edge.cfg.is_some() {
+                    cfg_links.push(links.to_string());
+                }
+                links += 1;
+            }
+            writeln!(out, "        end").unwrap();
+        }
+        writeln!(out, "    end").unwrap();
+    }
+    if !cfg_links.is_empty() {
+        writeln!(
+            out,
+            "    linkStyle {} stroke:{CFG_COLOUR},color:{CFG_COLOUR}",
+            cfg_links.join(",")
+        )
+        .unwrap();
+    }
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Return the `mod` declarations within a target, whose nodes are numbered by `t`.
+fn edges(t: usize, modules: &ModuleTree) -> Vec<Edge> {
+    let index = modules
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _))| (id, i))
+        .collect::<HashMap<_, _>>();
+    let mut edges = Vec::new();
+    for (i, (_, node)) in modules.iter().enumerate() {
+        for &child in &node.children {
+            let j = index[&child];
+            let child = &modules[child];
+            let cfg = (!child.cfgs.is_empty()).then(|| {
+                child
+                    .cfgs
+                    .iter()
+                    .map(|cfg| format!("cfg({cfg})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            });
+            edges.push(Edge {
+                from: format!("m{t}_{i}"),
+                to: format!("m{t}_{j}"),
+                path: child.has_path_attribute,
+                cfg,
+            });
+        }
+    }
+    edges
+}
+
+/// Return the lines labelling a module: its name, and its file if it has one.
+fn node_label(node: &ModuleNode, output: &Output) -> Vec<String> {
+    let mut lines = vec![node.name.clone()];
+    lines.extend(
+        node.file()
+            .map(|file| output.path(file).display().to_string()),
+    );
+    lines
+}
+
+fn describe_target(target: &Target) -> String {
+    format!("{} {}", target.kind, target.name)
+}
+
+/// Escape a string for use within a quoted DOT string.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escape a string for use within a quoted Mermaid label.
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape_dot(r#"cfg(feature="a\b")"#),
+            r#"cfg(feature=\"a\\b\")"#
+        );
+        assert_eq!(
+            escape_mermaid(r#"cfg(feature="<a>")"#),
+            "cfg(feature=#quot;#lt;a#gt;#quot;)"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! The JSON representation of the files of a crate, as printed with `--format json` or
+//! `--format jsonl`.
+//!
+//! Changes which could break existing consumers, such as renaming or removing a field, must
+//! increase [`SCHEMA_VERSION`]. Adding a field needn't.
+
+use cargo_files_core::Owner;
+use serde_json::{Value, json};
+use std::path::Path;
+
+/// The version of the schema of the JSON output.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Return the JSON object describing a file and the targets using it.
+///
+/// ```json
+/// {
+///   "path": "/home/user/foo/src/net/tcp.rs",
+///   "owners": [
+///     {
+///       "package": "foo",
+///       "package_id": "path+file:///home/user/foo#0.1.0",
+///       "target": { "kind": "lib", "name": "foo", "edition": "2021" },
+///       "module_path": "crate::net::tcp",
+///       "reached_by": "mod"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `reached_by` is one of `root`, `mod`, `path` (a `mod` declaration with a `#[path]`
+/// attribute) or `include` (an `include!` invocation).
+pub fn file(path: &Path, owners: &[Owner]) -> Value {
+    let owners = owners
+        .iter()
+        .map(|owner| {
+            json!({
+                "package": owner.target.package,
+                "package_id": owner.target.package_id,
+                "target": {
+                    "kind": owner.target.kind,
+                    "name": owner.target.name,
+                    "edition": owner.target.edition,
+                },
+                "module_path": owner.module_path,
+                "reached_by": owner.reached_by,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "path": path, "owners": owners })
+}
@@ -1,7 +1,24 @@
-use cargo_files_core::{get_target_files, get_targets, Error};
-use clap::Parser;
-use std::collections::HashSet;
+mod graph;
+mod json;
+mod output;
+mod render;
+mod tree;
+mod watch;
+
+use cargo_files_core::{
+    CfgOption, CfgSet, Diagnostic, Error, FeatureSelection, Options, OwnerIndex, ParseMode,
+    PathStyle, ReachedBy, Session, Severity, Target, TargetFilter, TargetInputs, TargetSelection,
+    find_orphan_files, get_all_target_inputs, get_cache_dir, get_targets, get_targets_cached,
+    get_workspace_root,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use graph::GraphFormat;
+use output::Output;
+use std::collections::{BTreeSet, HashSet};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use watch::WatchFormat;
 
 /// List all files in a cargo crate.
 #[derive(Debug, Parser)]
@@ -14,27 +31,555 @@ use std::path::PathBuf;
     .hide(true))
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// How to print the list of files
+    #[arg(long, value_enum, default_value_t)]
+    format: ListFormat,
+
+    /// List the files of each target separately, under a heading naming the target
+    #[arg(long, value_enum, conflicts_with = "format")]
+    group_by: Option<GroupBy>,
+
     /// Path to Cargo.toml
-    #[arg(long)]
+    #[arg(long, global = true)]
     manifest_path: Option<PathBuf>,
+
+    /// Keep going after unparseable files or missing modules, reporting them at the end
+    #[arg(long, global = true)]
+    keep_going: bool,
+
+    /// Treat warnings, such as modules found at both foo.rs and foo/mod.rs, as errors
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Number of threads used to parse files [default: number of CPUs]
+    #[arg(short, long, global = true)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Parse every file, rather than reusing results cached in target/cargo-files by previous runs
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Parse every file in full, warning about any syntax error, rather than only scanning for
+    /// module declarations. Without this, only syntax errors which stop a file from being
+    /// tokenized are reported, and no file is reported as parsed with recovery
+    #[arg(long, global = true)]
+    full_parse: bool,
+
+    /// End each line of output with NUL rather than a newline, as expected by `xargs -0`
+    #[arg(short = '0', long, global = true)]
+    print0: bool,
+
+    /// Print paths relative to the workspace root
+    #[arg(long, global = true, conflicts_with = "relative_to")]
+    relative: bool,
+
+    /// Print paths relative to the given directory
+    #[arg(long, value_name = "DIR", global = true)]
+    relative_to: Option<PathBuf>,
+
+    #[command(flatten)]
+    targets: TargetArgs,
+
+    #[command(flatten)]
+    features: FeatureArgs,
+
+    /// Only use the modules compiled for the given target triple
+    #[arg(
+        long,
+        value_name = "TRIPLE",
+        global = true,
+        help_heading = "Compilation Options"
+    )]
+    target: Option<String>,
+
+    /// Set a cfg option when deciding which modules are compiled, such as `foo` or
+    /// `key="value"`
+    #[arg(
+        long = "cfg",
+        value_name = "SPEC",
+        value_parser = parse_cfg_option,
+        global = true,
+        help_heading = "Compilation Options"
+    )]
+    cfgs: Vec<CfgOption>,
+}
+
+impl Args {
+    /// Return the cfg set modules are selected with, if any flag asks for a specific build.
+    ///
+    /// Telling which modules a build compiles requires all of its cfg options, so these are
+    /// taken from rustc for the host or the given target.
+    fn cfg(&self) -> Result<Option<CfgSet>, Error> {
+        if self.features.is_empty() && self.target.is_none() && self.cfgs.is_empty() {
+            return Ok(None);
+        }
+
+        let mut cfg = CfgSet::from_rustc(self.target.as_deref())?;
+        cfg.extend(self.cfgs.iter().cloned());
+        Ok(Some(cfg))
+    }
+
+    /// Return how output is printed.
+    fn output(&self) -> Result<Output, Error> {
+        let style = if self.relative {
+            PathStyle::RelativeTo(get_workspace_root(self.manifest_path.as_deref())?)
+        } else if let Some(dir) = &self.relative_to {
+            PathStyle::relative_to(dir)
+        } else {
+            PathStyle::Absolute
+        };
+        Ok(Output {
+            style,
+            print0: self.print0,
+        })
+    }
+}
+
+fn parse_cfg_option(spec: &str) -> Result<CfgOption, String> {
+    CfgOption::parse(spec)
+        .ok_or_else(|| String::from("expected a name such as `foo`, or `key=\"value\"`"))
+}
+
+/// Cargo's feature selection flags. With any of them, only the modules compiled with the
+/// selected features are used, rather than every module regardless of cfg.
+#[derive(Debug, clap::Args)]
+#[command(next_help_heading = "Feature Selection")]
+struct FeatureArgs {
+    /// Space or comma separated list of features to activate
+    #[arg(short = 'F', long, value_name = "FEATURES", global = true)]
+    features: Vec<String>,
+
+    /// Activate all available features of all selected packages
+    #[arg(long, global = true)]
+    all_features: bool,
+
+    /// Do not activate the `default` feature of the selected packages
+    #[arg(long, global = true)]
+    no_default_features: bool,
+}
+
+impl FeatureArgs {
+    fn is_empty(&self) -> bool {
+        self.features.is_empty() && !self.all_features && !self.no_default_features
+    }
+
+    fn selection(&self) -> FeatureSelection {
+        FeatureSelection {
+            features: self
+                .features
+                .iter()
+                .flat_map(|features| features.split([' ', ',']))
+                .filter(|feature| !feature.is_empty())
+                .map(String::from)
+                .collect(),
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+        }
+    }
+}
+
+/// Cargo's target selection flags. Without any, every target of the workspace and of its path
+/// dependencies is used.
+#[derive(Debug, clap::Args)]
+#[command(next_help_heading = "Package Selection")]
+struct TargetArgs {
+    /// Only use targets of the given packages
+    #[arg(short, long = "package", value_name = "SPEC", global = true)]
+    packages: Vec<String>,
+
+    /// Only use targets of workspace members, rather than of path dependencies too
+    #[arg(long, global = true)]
+    workspace: bool,
+
+    /// Exclude targets of the given packages
+    #[arg(long, value_name = "SPEC", requires = "workspace", global = true)]
+    exclude: Vec<String>,
+
+    /// Use the library target
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    lib: bool,
+
+    /// Use the given binary targets
+    #[arg(
+        long = "bin",
+        value_name = "NAME",
+        global = true,
+        help_heading = "Target Selection"
+    )]
+    bin_names: Vec<String>,
+
+    /// Use all binary targets
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    bins: bool,
+
+    /// Use the given example targets
+    #[arg(
+        long = "example",
+        value_name = "NAME",
+        global = true,
+        help_heading = "Target Selection"
+    )]
+    example_names: Vec<String>,
+
+    /// Use all example targets
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    examples: bool,
+
+    /// Use the given integration test targets
+    #[arg(
+        long = "test",
+        value_name = "NAME",
+        global = true,
+        help_heading = "Target Selection"
+    )]
+    test_names: Vec<String>,
+
+    /// Use all integration test targets
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    tests: bool,
+
+    /// Use the given benchmark targets
+    #[arg(
+        long = "bench",
+        value_name = "NAME",
+        global = true,
+        help_heading = "Target Selection"
+    )]
+    bench_names: Vec<String>,
+
+    /// Use all benchmark targets
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    benches: bool,
+
+    /// Use all library, binary, example, test and benchmark targets
+    #[arg(long, global = true, help_heading = "Target Selection")]
+    all_targets: bool,
+}
+
+impl TargetArgs {
+    fn filter(&self) -> TargetFilter {
+        let selection = |all: bool, names: &[String]| {
+            if all || self.all_targets {
+                TargetSelection::All
+            } else if names.is_empty() {
+                TargetSelection::None
+            } else {
+                TargetSelection::Named(names.to_vec())
+            }
+        };
+
+        TargetFilter {
+            packages: self.packages.clone(),
+            workspace: self.workspace,
+            exclude: self.exclude.clone(),
+            lib: self.lib || self.all_targets,
+            bins: selection(self.bins, &self.bin_names),
+            examples: selection(self.examples, &self.example_names),
+            tests: selection(self.tests, &self.test_names),
+            benches: selection(self.benches, &self.bench_names),
+        }
+    }
+}
+
+/// How the list of files is printed.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum ListFormat {
+    /// The path of each file, one per line
+    #[default]
+    Plain,
+    /// A single JSON object listing each file, the targets using it, and how they reach it
+    Json,
+    /// Like `json`, but with one JSON object per file, on its own line
+    Jsonl,
+}
+
+/// How the list of files is grouped.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum GroupBy {
+    /// A group for each target of each package
+    Target,
 }
 
-fn main() -> Result<(), Error> {
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Show which targets use the given files, and as which module
+    Owner {
+        /// The files to look up
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// List Rust source files in each package which aren't used by any target
+    Orphans,
+    /// Print the module hierarchy of each target, with the file of each module
+    Tree,
+    /// Print the module hierarchy of each target as a graph, for rendering with Graphviz or Mermaid
+    Graph {
+        /// The language to print the graph in
+        #[arg(long, value_enum, default_value_t)]
+        format: GraphFormat,
+    },
+    /// List all files, then print files as they are added to or removed from any target
+    Watch {
+        /// How to print changes
+        #[arg(long, value_enum, default_value_t)]
+        format: WatchFormat,
+    },
+}
+
+fn main() -> ExitCode {
     let args: Args = Args::parse();
 
+    // Errors finding how to print output are reported with the default path style.
+    let (output, result) = match args.output() {
+        Ok(output) => {
+            let result = run(&args, &output);
+            (output, result)
+        }
+        Err(error) => (Output::default(), Err(error)),
+    };
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(error) => {
+            eprint!(
+                "{}",
+                render::render_error(&error, &output.diagnostic_style())
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args, output: &Output) -> Result<ExitCode, Error> {
+    let cfg = args.cfg()?;
+    let options = Options {
+        keep_going: args.keep_going,
+        strict: args.strict,
+        jobs: args.jobs,
+        cache_dir: if args.no_cache {
+            None
+        } else {
+            Some(get_cache_dir(args.manifest_path.as_deref())?)
+        },
+        parse_mode: if args.full_parse {
+            ParseMode::Full
+        } else {
+            ParseMode::Fast
+        },
+        // A build enables the default features unless told otherwise.
+        features: cfg.is_some().then(|| args.features.selection()),
+        cfg,
+    };
+
+    let filter = args.targets.filter();
+    let load_targets = || {
+        let targets = match &options.cache_dir {
+            Some(cache_dir) => get_targets_cached(args.manifest_path.as_deref(), cache_dir)?,
+            None => get_targets(args.manifest_path.as_deref())?,
+        };
+        filter.apply(targets)
+    };
+
+    if let Some(Command::Watch { format }) = &args.command {
+        let session = Session::new(options.clone());
+        return match watch::watch(session, load_targets, output, *format) {
+            Ok(()) => Ok(ExitCode::SUCCESS),
+            Err(error) => {
+                eprintln!("error: failed to watch files: {error}");
+                Ok(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    let targets = load_targets()?;
+    let inputs = get_all_target_inputs(&targets, &options)?;
+
+    let success = match &args.command {
+        None => match args.group_by {
+            Some(GroupBy::Target) => list_files_by_target(&targets, &inputs, output),
+            None => list_files(&targets, &inputs, output, args.format),
+        },
+        Some(Command::Owner { files }) => list_owners(&targets, &inputs, output, files),
+        Some(Command::Orphans) => list_orphans(&targets, &inputs, output),
+        Some(Command::Tree) => print_trees(&targets, &inputs, output),
+        Some(Command::Graph { format }) => {
+            let modules = targets
+                .iter()
+                .zip(&inputs)
+                .map(|(target, inputs)| (target, &inputs.modules))
+                .collect::<Vec<_>>();
+            output.print(graph::render(&modules, output, *format));
+            true
+        }
+        Some(Command::Watch { .. }) => unreachable!("handled above"),
+    };
+
+    // A problem in a shared file is reported by every target using it.
+    let diagnostics = inputs.iter().flat_map(|inputs| &inputs.diagnostics);
+    let failed = report_diagnostics(diagnostics, &output.diagnostic_style());
+
+    Ok(if success && !failed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// List every source file used by a target in the workspace.
+fn list_files(
+    targets: &BTreeSet<Target>,
+    inputs: &[TargetInputs],
+    output: &Output,
+    format: ListFormat,
+) -> bool {
     // Note that multiple targets may end up using the same files (e.g. tests);
     // only include each file in the output once.
-    let targets = get_targets(args.manifest_path.as_deref())?;
-    let mut files = HashSet::new();
-    for target in targets {
-        files.extend(get_target_files(&target)?);
+    let mut files = inputs
+        .iter()
+        .flat_map(|inputs| &inputs.sources)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let owners = OwnerIndex::new(targets.iter().zip(inputs));
+    match format {
+        ListFormat::Plain => {
+            for file in files {
+                output.print(output.path(file).display());
+            }
+        }
+        ListFormat::Json => {
+            let files = files
+                .into_iter()
+                .map(|file| json::file(&output.path(file), owners.get(file)))
+                .collect::<Vec<_>>();
+            output.print(serde_json::json!({ "version": json::SCHEMA_VERSION, "files": files }));
+        }
+        ListFormat::Jsonl => {
+            for file in files {
+                let mut line = json::file(&output.path(file), owners.get(file));
+                line["version"] = json::SCHEMA_VERSION.into();
+                output.print(line);
+            }
+        }
     }
+    true
+}
 
-    let mut files = files.into_iter().collect::<Vec<_>>();
-    files.sort();
+/// List the source files of each target under a heading naming the target.
+fn list_files_by_target(
+    targets: &BTreeSet<Target>,
+    inputs: &[TargetInputs],
+    output: &Output,
+) -> bool {
+    for (i, (target, inputs)) in targets.iter().zip(inputs).enumerate() {
+        if i > 0 {
+            output.print("");
+        }
+        output.print(format_args!("{}:", describe_target(target)));
+
+        let mut files = inputs.sources.iter().collect::<Vec<_>>();
+        files.sort();
+        for file in files {
+            output.print(format_args!("    {}", output.path(file).display()));
+        }
+    }
+    true
+}
+
+/// Print the module hierarchy of each target under a heading naming the target.
+fn print_trees(targets: &BTreeSet<Target>, inputs: &[TargetInputs], output: &Output) -> bool {
+    let uses = tree::count_uses(inputs.iter().map(|inputs| &inputs.modules));
+    for (i, (target, inputs)) in targets.iter().zip(inputs).enumerate() {
+        if i > 0 {
+            output.print("");
+        }
+        output.print(format_args!("{}:", describe_target(target)));
+        tree::print_tree(output, &inputs.modules, &uses);
+    }
+    true
+}
+
+/// Describe a target, as in "lib target `foo` of package `foo`".
+fn describe_target(target: &Target) -> String {
+    format!(
+        "{} target `{}` of package `{}`",
+        target.kind, target.name, target.package
+    )
+}
+
+/// List the targets using each of the given files, and the module each file belongs to.
+///
+/// Returns false if any of the files aren't used by a target.
+fn list_owners(
+    targets: &BTreeSet<Target>,
+    inputs: &[TargetInputs],
+    output: &Output,
+    files: &[PathBuf],
+) -> bool {
+    let index = OwnerIndex::new(targets.iter().zip(inputs));
+    let style = output.diagnostic_style();
+    let mut success = true;
     for file in files {
-        println!("{}", file.display());
+        // Canonicalize the file as given, so that it can be printed relative to another directory.
+        let file = &dunce::canonicalize(file).unwrap_or_else(|_| file.clone());
+        let owners = index.get(file);
+        if owners.is_empty() {
+            eprintln!(
+                "error: {} is not used by any target",
+                style.apply(file).display()
+            );
+            success = false;
+        }
+        for owner in owners {
+            output.print(format_args!(
+                "{}: {}{} in {}",
+                output.path(file).display(),
+                owner.module_path,
+                if owner.reached_by == ReachedBy::Include {
+                    " (included)"
+                } else {
+                    ""
+                },
+                describe_target(owner.target),
+            ));
+        }
+    }
+    success
+}
+
+/// List the Rust source files in each package which aren't used by any target.
+///
+/// Returns false if there are any such files.
+fn list_orphans(targets: &BTreeSet<Target>, inputs: &[TargetInputs], output: &Output) -> bool {
+    let used = inputs
+        .iter()
+        .flat_map(|inputs| inputs.sources.iter().cloned())
+        .collect::<HashSet<_>>();
+    let orphans = find_orphan_files(targets, &used);
+    for file in &orphans {
+        output.print(output.path(file).display());
+    }
+    orphans.is_empty()
+}
+
+/// Print each distinct diagnostic, returning true if any of them are errors.
+fn report_diagnostics<'a>(
+    diagnostics: impl Iterator<Item = &'a Diagnostic>,
+    style: &PathStyle,
+) -> bool {
+    let mut reported = HashSet::new();
+    let mut failed = false;
+    for diagnostic in diagnostics {
+        let rendered = render::render_diagnostic(diagnostic, style);
+        if reported.insert(rendered.clone()) {
+            eprintln!("{rendered}");
+        }
+        failed |= diagnostic.severity == Severity::Error;
     }
 
-    Ok(())
+    failed
 }
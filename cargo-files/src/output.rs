@@ -0,0 +1,47 @@
+//! Printing records, such as the paths of files, to stdout.
+
+use cargo_files_core::PathStyle;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::path::Path;
+
+/// How records are printed: how the paths within them are presented, and what ends each one.
+///
+/// Every path printed to stdout goes through [`Output::path`], so that `--relative` and
+/// `--relative-to` apply to every format. Diagnostics printed to stderr use
+/// [`Output::diagnostic_style`].
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub style: PathStyle,
+    /// End records with NUL rather than a newline, as with `--print0`.
+    pub print0: bool,
+}
+
+impl Output {
+    /// Return the path to print for the given path.
+    pub fn path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        self.style.apply(path)
+    }
+
+    /// Return how paths are presented in diagnostics.
+    ///
+    /// Like rustc, diagnostics present paths relative to the current directory, unless
+    /// `--relative` or `--relative-to` chose another directory.
+    pub fn diagnostic_style(&self) -> PathStyle {
+        match &self.style {
+            PathStyle::Absolute => std::env::current_dir()
+                .map(|cwd| PathStyle::relative_to(&cwd))
+                .unwrap_or_default(),
+            style => style.clone(),
+        }
+    }
+
+    /// Print a record, followed by a newline or NUL.
+    pub fn print(&self, record: impl Display) {
+        if self.print0 {
+            print!("{record}\0");
+        } else {
+            println!("{record}");
+        }
+    }
+}
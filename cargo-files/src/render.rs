@@ -0,0 +1,144 @@
+use cargo_files_core::{Diagnostic, Error, PathStyle, Severity, Span};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Render an error in the style of a rustc diagnostic, presenting paths in the given style.
+pub fn render_error(error: &Error, style: &PathStyle) -> String {
+    render(Severity::Error, error, style)
+}
+
+/// Render a diagnostic in the style of a rustc diagnostic, presenting paths in the given style.
+pub fn render_diagnostic(diagnostic: &Diagnostic, style: &PathStyle) -> String {
+    render(diagnostic.severity, &diagnostic.error, style)
+}
+
+fn render(severity: Severity, error: &Error, style: &PathStyle) -> String {
+    let (message, notes) = match error {
+        Error::ModuleNotFound {
+            name, candidates, ..
+        } => {
+            let candidates = candidates
+                .iter()
+                .map(|candidate| style.apply(candidate).display().to_string())
+                .collect::<Vec<_>>();
+            (
+                format!("could not find module `{name}`"),
+                vec![format!("help: searched for {}", candidates.join(", "))],
+            )
+        }
+        Error::AmbiguousModule {
+            name, candidates, ..
+        } => {
+            let candidates = candidates
+                .iter()
+                .map(|candidate| style.apply(candidate).display().to_string())
+                .collect::<Vec<_>>();
+            (
+                format!(
+                    "file for module `{name}` found at both {}",
+                    candidates.join(" and ")
+                ),
+                vec![String::from(
+                    "help: delete or rename one of them to remove the ambiguity",
+                )],
+            )
+        }
+        Error::IncludeNotFound { path, .. } => (
+            format!("could not find `{}`", style.apply(path).display()),
+            vec![],
+        ),
+        Error::ParseError { message, .. } => (message.clone(), vec![]),
+        error => (error.to_string(), vec![]),
+    };
+
+    let location = error.file().zip(error.span());
+    render_message(&severity.to_string(), &message, location, &notes, style)
+}
+
+/// Render a message, with a snippet of the source file at the given location if possible.
+fn render_message(
+    severity: &str,
+    message: &str,
+    location: Option<(&Path, Span)>,
+    notes: &[String],
+    style: &PathStyle,
+) -> String {
+    let mut out = format!("{severity}: {message}\n");
+
+    let source = location.and_then(|(file, _)| fs::read_to_string(file).ok());
+    let width = match location {
+        Some((_, span)) => span.end.line.to_string().len(),
+        None => 1,
+    };
+    let gutter = " ".repeat(width);
+
+    if let Some((file, span)) = location {
+        writeln!(
+            out,
+            "{gutter}--> {}:{}",
+            style.apply(file).display(),
+            span.start
+        )
+        .unwrap();
+        if let Some(source) = &source {
+            writeln!(out, "{gutter} |").unwrap();
+            out.push_str(&render_snippet(source, span, width));
+        }
+    }
+
+    if !notes.is_empty() {
+        writeln!(out, "{gutter} |").unwrap();
+        for note in notes {
+            writeln!(out, "{gutter} = {note}").unwrap();
+        }
+    }
+
+    out
+}
+
+/// Render the first line of the given span, with the spanned region underlined.
+fn render_snippet(source: &str, span: Span, width: usize) -> String {
+    let Some(line) = source.lines().nth(span.start.line.saturating_sub(1)) else {
+        return String::new();
+    };
+
+    let length = line.chars().count();
+    let start = span.start.column.min(length);
+    let end = if span.end.line == span.start.line {
+        span.end.column.min(length)
+    } else {
+        length
+    };
+    let carets = "^".repeat(end.saturating_sub(start).max(1));
+
+    format!(
+        "{:>width$} | {line}\n{:width$} | {}{carets}\n",
+        span.start.line,
+        "",
+        " ".repeat(start),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_files_core::LineColumn;
+
+    #[test]
+    fn test_render_snippet() {
+        let source = "// comment\npub mod foo;\n";
+        let span = Span {
+            start: LineColumn { line: 2, column: 4 },
+            end: LineColumn {
+                line: 2,
+                column: 12,
+            },
+        };
+
+        assert_eq!(
+            render_snippet(source, span, 1),
+            "2 | pub mod foo;\n  |     ^^^^^^^^\n"
+        );
+    }
+}
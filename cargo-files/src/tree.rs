@@ -0,0 +1,94 @@
+//! Printing the module hierarchy of a target, in the style of `cargo tree`.
+
+use crate::output::Output;
+use cargo_files_core::{ModuleId, ModuleNode, ModuleTree};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Print the module hierarchy of a target, one module per line.
+///
+/// Each module is followed by its file, and marked if it is `[inline]`, if its file was chosen
+/// using a `#[path]` attribute (`[path]`), or if its file is used by several targets or modules
+/// (`[shared]`). `uses` counts the modules using each file, across every target.
+pub fn print_tree(output: &Output, modules: &ModuleTree, uses: &HashMap<&Path, usize>) {
+    print_module(
+        output,
+        modules,
+        modules.root(),
+        uses,
+        &mut String::new(),
+        None,
+    );
+}
+
+/// Count the modules using each file across the given module trees.
+pub fn count_uses<'a>(trees: impl IntoIterator<Item = &'a ModuleTree>) -> HashMap<&'a Path, usize> {
+    let mut uses = HashMap::new();
+    for modules in trees {
+        for (_, node) in modules.iter() {
+            if let Some(file) = node.file() {
+                *uses.entry(file).or_default() += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// Print a module and its children.
+///
+/// `prefix` holds the guides of the enclosing modules, and `last` is whether the module is the
+/// last child of its parent, or `None` for the crate root.
+fn print_module(
+    output: &Output,
+    modules: &ModuleTree,
+    id: ModuleId,
+    uses: &HashMap<&Path, usize>,
+    prefix: &mut String,
+    last: Option<bool>,
+) {
+    let node = &modules[id];
+    let branch = match last {
+        None => "",
+        Some(false) => "├── ",
+        Some(true) => "└── ",
+    };
+    output.print(format_args!(
+        "{prefix}{branch}{}",
+        describe(output, node, uses)
+    ));
+
+    let length = prefix.len();
+    match last {
+        None => {}
+        Some(false) => prefix.push_str("│   "),
+        Some(true) => prefix.push_str("    "),
+    }
+    for (i, &child) in node.children.iter().enumerate() {
+        let last = i + 1 == node.children.len();
+        print_module(output, modules, child, uses, prefix, Some(last));
+    }
+    prefix.truncate(length);
+}
+
+/// Describe a module by its name, its file and its markers.
+fn describe(output: &Output, node: &ModuleNode, uses: &HashMap<&Path, usize>) -> String {
+    let mut description = node.name.clone();
+    let mut markers = Vec::new();
+    match node.file() {
+        Some(file) => {
+            write!(description, " ({})", output.path(file).display()).unwrap();
+            if uses.get(file).is_some_and(|&count| count > 1) {
+                markers.push("shared");
+            }
+        }
+        None => markers.push("inline"),
+    }
+    if node.has_path_attribute {
+        markers.insert(0, "path");
+    }
+    if !markers.is_empty() {
+        write!(description, " [{}]", markers.join(", ")).unwrap();
+    }
+    description
+}
@@ -0,0 +1,226 @@
+use crate::output::Output;
+use crate::render;
+use cargo_files_core::{Error, Session, Target};
+use clap::ValueEnum;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long to wait for more changes after one is seen, so that a burst of changes (e.g. from
+/// switching branches) is handled at once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How changes to the set of files are printed.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum WatchFormat {
+    /// `+ path` for each added file and `- path` for each removed file
+    #[default]
+    Plain,
+    /// One JSON object per line, such as `{"event":"added","path":"..."}`
+    Json,
+}
+
+/// Print the files of every target, then print changes to them as files are edited, until
+/// interrupted.
+///
+/// `load_targets` is called whenever anything changes, in case the targets themselves have.
+/// Problems collecting files are reported, and collection is tried again after the next change;
+/// only a failure to watch files is returned.
+pub fn watch(
+    session: Session,
+    load_targets: impl Fn() -> Result<BTreeSet<Target>, Error>,
+    output: &Output,
+    format: WatchFormat,
+) -> notify::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut watched = BTreeSet::new();
+    let mut watched_dirs = BTreeSet::new();
+
+    let mut files = BTreeSet::new();
+    let mut reported = HashSet::new();
+    loop {
+        match collect(&session, &load_targets, output, &mut reported) {
+            Ok((targets, new_files)) => {
+                print_changes(&files, &new_files, output, format);
+                files = new_files;
+
+                // Watch the directory of any new package, such as a new workspace member.
+                for target in &targets {
+                    let Some(root) = target.manifest_path.parent() else {
+                        continue;
+                    };
+                    let root = canonicalize(root);
+                    if watched.insert(root.clone()) {
+                        watcher.watch(&root, RecursiveMode::Recursive)?;
+                    }
+                }
+
+                // Files reached using `#[path]` or `include!` may be outside every package, so
+                // their directories are watched too, though not recursively.
+                for dir in files.iter().filter_map(|file| file.parent()) {
+                    if !watched.iter().any(|root| dir.starts_with(root))
+                        && watched_dirs.insert(dir.to_path_buf())
+                    {
+                        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                    }
+                }
+            }
+            // Report the problem and wait for it to be fixed.
+            Err(error) => eprint!(
+                "{}",
+                render::render_error(&error, &output.diagnostic_style())
+            ),
+        }
+
+        let changed = wait_for_changes(&receiver, &watched)?;
+        for path in changed {
+            session.forget(&path);
+        }
+    }
+}
+
+/// Collect the files of every target, printing any diagnostics which weren't printed last time.
+fn collect(
+    session: &Session,
+    load_targets: impl Fn() -> Result<BTreeSet<Target>, Error>,
+    output: &Output,
+    reported: &mut HashSet<String>,
+) -> Result<(BTreeSet<Target>, BTreeSet<PathBuf>), Error> {
+    let targets = load_targets()?;
+    let inputs = session.all_target_inputs(&targets)?;
+
+    let style = output.diagnostic_style();
+    let mut diagnostics = HashSet::new();
+    for diagnostic in inputs.iter().flat_map(|inputs| &inputs.diagnostics) {
+        let rendered = render::render_diagnostic(diagnostic, &style);
+        if !reported.contains(&rendered) && !diagnostics.contains(&rendered) {
+            eprintln!("{rendered}");
+        }
+        diagnostics.insert(rendered);
+    }
+    *reported = diagnostics;
+
+    let files = inputs
+        .into_iter()
+        .flat_map(|inputs| inputs.sources)
+        .collect();
+    Ok((targets, files))
+}
+
+fn print_changes(
+    old: &BTreeSet<PathBuf>,
+    new: &BTreeSet<PathBuf>,
+    output: &Output,
+    format: WatchFormat,
+) {
+    let removed = old.difference(new).map(|path| ("removed", "-", path));
+    let added = new.difference(old).map(|path| ("added", "+", path));
+    for (event, sign, path) in removed.chain(added) {
+        let path = output.path(path);
+        match format {
+            WatchFormat::Plain => output.print(format_args!("{sign} {}", path.display())),
+            WatchFormat::Json => output.print(serde_json::json!({ "event": event, "path": path })),
+        }
+    }
+}
+
+/// Block until a relevant file changes, returning every path which changed.
+fn wait_for_changes(
+    receiver: &mpsc::Receiver<notify::Result<notify::Event>>,
+    watched: &BTreeSet<PathBuf>,
+) -> notify::Result<BTreeSet<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    let mut next = receiver.recv().map_err(|_| disconnected())?;
+    loop {
+        let event = next?;
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            changed.extend(
+                event
+                    .paths
+                    .iter()
+                    .map(|path| canonicalize(path))
+                    .filter(|path| !is_ignored(path, watched)),
+            );
+        }
+
+        next = if changed.is_empty() {
+            receiver.recv().map_err(|_| disconnected())?
+        } else {
+            match receiver.recv_timeout(DEBOUNCE) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(changed),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(disconnected()),
+            }
+        };
+    }
+}
+
+fn disconnected() -> notify::Error {
+    notify::Error::generic("the watcher stopped unexpectedly")
+}
+
+/// Canonicalize a path, as the paths of files are, so that a change to a file reached through a
+/// symlink (e.g. `/tmp` on macOS) is matched with the file.
+///
+/// A deleted file can't be canonicalized, so its parent directory is canonicalized instead.
+fn canonicalize(path: &Path) -> PathBuf {
+    if let Ok(canonical) = dunce::canonicalize(path) {
+        return canonical;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => dunce::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Return true for changes which can't affect the files of any target, such as those made by
+/// builds (including writes to our own cache) or to version control metadata.
+fn is_ignored(path: &Path, watched: &BTreeSet<PathBuf>) -> bool {
+    watched.iter().any(|root| {
+        path.strip_prefix(root).is_ok_and(|relative| {
+            relative
+                .components()
+                .next()
+                .is_some_and(|first| first.as_os_str() == "target" || first.as_os_str() == ".git")
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let watched = BTreeSet::from([PathBuf::from("/ws"), PathBuf::from("/other")]);
+        assert!(is_ignored(Path::new("/ws/target/debug/foo.rs"), &watched));
+        assert!(is_ignored(Path::new("/other/.git/index"), &watched));
+        assert!(!is_ignored(Path::new("/ws/src/target.rs"), &watched));
+        assert!(!is_ignored(Path::new("/ws/src/target/mod.rs"), &watched));
+        assert!(!is_ignored(Path::new("/elsewhere/target/foo.rs"), &watched));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_canonicalize_through_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        let link = dir.path().join("link");
+        std::fs::create_dir_all(&real).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        std::fs::write(real.join("lib.rs"), "").unwrap();
+
+        let real = dunce::canonicalize(&real).unwrap();
+        assert_eq!(canonicalize(&link.join("lib.rs")), real.join("lib.rs"));
+        // Deleted files are found through their parent directory.
+        assert_eq!(canonicalize(&link.join("gone.rs")), real.join("gone.rs"));
+    }
+}
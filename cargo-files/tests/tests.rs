@@ -0,0 +1,88 @@
+use std::process::Command;
+
+/// Run cargo-files on the given crate with paths relative to its root, returning its output.
+fn cargo_files(krate: &tempfile::TempDir, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-files"))
+        .args(args)
+        .arg("--manifest-path")
+        .arg(krate.path().join("Cargo.toml"))
+        .args(["--relative", "--no-cache"])
+        .output()
+        .expect("failed to run cargo-files");
+    assert!(
+        output.status.success(),
+        "cargo-files failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("output should be UTF-8")
+}
+
+/// A crate with a module found using `#[path]`, an inline module containing a cfg-gated module,
+/// and a module shared by two integration tests.
+fn fixture() -> tempfile::TempDir {
+    ::cargo_files_test::make_crate!(
+        r##"
+        src:
+          - lib.rs: |
+              #[path = "unix.rs"]
+              mod imp;
+              mod inner {
+                  #[cfg(feature = "deep")]
+                  mod deep;
+              }
+          - unix.rs
+          - inner:
+            - deep.rs
+        tests:
+          - a.rs [common]
+          - b.rs [common]
+          - common:
+            - mod.rs
+    "##
+    )
+}
+
+#[test]
+fn tree() {
+    insta::assert_snapshot!(cargo_files(&fixture(), &["tree"]));
+}
+
+#[test]
+fn group_by_target() {
+    insta::assert_snapshot!(cargo_files(&fixture(), &["--group-by", "target"]));
+}
+
+#[test]
+fn graph_dot() {
+    insta::assert_snapshot!(cargo_files(&fixture(), &["graph", "--format", "dot"]));
+}
+
+#[test]
+fn graph_mermaid() {
+    insta::assert_snapshot!(cargo_files(&fixture(), &["graph", "--format", "mermaid"]));
+}
+
+#[test]
+fn owner() {
+    let krate = fixture();
+    let file = krate.path().join("src/unix.rs");
+    insta::assert_snapshot!(cargo_files(&krate, &["owner", file.to_str().unwrap()]));
+}
+
+#[test]
+fn owner_of_unused_file() {
+    let krate = fixture();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-files"))
+        .arg("owner")
+        .arg(krate.path().join("Cargo.toml"))
+        .arg("--manifest-path")
+        .arg(krate.path().join("Cargo.toml"))
+        .args(["--relative", "--no-cache"])
+        .output()
+        .expect("failed to run cargo-files");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: Cargo.toml is not used by any target\n"
+    );
+}